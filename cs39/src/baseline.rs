@@ -0,0 +1,456 @@
+use crate::{
+    navigate::repo_dir,
+    output::{csv_path, TableWriter, INFO_INDENT},
+    metadata::{rfc3339_utc, sidecar_path},
+    stats::{mann_whitney_p, percentile, SampleRow},
+    units::Millis,
+    error::{Error, Result},
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{create_dir_all, read_to_string, write},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use serde::{Deserialize, Serialize};
+
+/// Tasks whose results can be saved as baselines.
+pub const TASKS: &[&str] = &["cpu_test", "size_test"];
+
+/// Name of the baseline saved and compared against, unless specified
+/// otherwise.
+pub const DEFAULT_BASELINE: &str = "default";
+
+/// Iteration times of one configuration of a demo, excluding warm-up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigSamples {
+    /// Which configuration, eg. the thread count or dimensions.
+    pub config: String,
+    pub samples_ms: Vec<Millis>,
+}
+
+/// Saved timing distributions of a task on a demo.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub task: String,
+    pub major: u32,
+    pub minor: u32,
+    /// When the baseline was saved, as RFC 3339.
+    pub saved: String,
+    /// Raw samples table it was saved from.
+    pub source: PathBuf,
+    pub configs: Vec<ConfigSamples>,
+    /// Metadata sidecar of the samples table, if it had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<toml::value::Table>,
+}
+
+impl Baseline {
+    /// Commit of the demos checkout the baseline was run on, if known.
+    pub fn git_commit(&self) -> Option<&str> {
+        self.metadata.as_ref()?.get("git_commit")?.as_str()
+    }
+}
+
+/// Path of the raw samples table of a task, eg. `cpu_test_0_3_samples.csv`.
+pub fn samples_path(task: &str, major: u32, minor: u32) -> Result<PathBuf> {
+    csv_path(format!("{}_{}_{}_samples.csv", task, major, minor))
+}
+
+/// Path of a saved baseline of a task, eg. `baselines/default/cpu_test_0_3.toml`.
+pub fn baseline_path(name: &str, task: &str, major: u32, minor: u32) -> Result<PathBuf> {
+    Ok(repo_dir()?
+        .join("baselines")
+        .join(name)
+        .join(format!("{}_{}_{}.toml", task, major, minor)))
+}
+
+/// Read the samples of each configuration from a raw samples table, in
+/// order of first appearance, or `None` if there isn't one.
+pub fn read_samples(path: &Path) -> Result<Option<Vec<ConfigSamples>>> {
+    let mut reader = match csv::Reader::from_path(path) {
+        Ok(reader) => reader,
+        Err(e) => return match e.kind() {
+            csv::ErrorKind::Io(io) if io.kind() == ErrorKind::NotFound => Ok(None),
+            _ => Err(Error::InvalidTable {
+                path: path.to_owned(),
+                message: e.to_string(),
+            }),
+        },
+    };
+    let mut configs: Vec<ConfigSamples> = Vec::new();
+    for row in reader.deserialize::<SampleRow>() {
+        let row = row.map_err(|e| Error::InvalidTable {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })?;
+        if row.warmup {
+            continue;
+        }
+        match configs.iter_mut().find(|config| config.config == row.config) {
+            Some(config) => config.samples_ms.push(row.time_ms),
+            None => configs.push(ConfigSamples {
+                config: row.config,
+                samples_ms: vec![row.time_ms],
+            }),
+        }
+    }
+    Ok(Some(configs))
+}
+
+/// Read a saved baseline, or `None` if there isn't one.
+pub fn read_baseline(path: &Path) -> Result<Option<Baseline>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::io(path)(e)),
+    };
+    toml::from_str(&text)
+        .map(Some)
+        .map_err(|e| Error::InvalidTable {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })
+}
+
+/// `baseline save` task.
+///
+/// Saves the latest results of each of `tasks` on the demo, skipping tasks
+/// without results, and failing if none have any. Baselines are written to
+/// `baselines/` in the repo root, rather than the ignored `output/`, so they
+/// can be committed along with the demos.
+pub fn save(tasks: &[&str], name: &str, major: u32, minor: u32) -> Result<()> {
+    let mut searched = Vec::new();
+    for &task in tasks {
+        let source = samples_path(task, major, minor)?;
+        let configs = match read_samples(&source)? {
+            Some(configs) => configs,
+            None => {
+                searched.push(source);
+                continue;
+            },
+        };
+        let metadata = read_to_string(sidecar_path(&source)).ok()
+            .and_then(|text| toml::from_str(&text).ok());
+        let baseline = Baseline {
+            task: task.to_owned(),
+            major,
+            minor,
+            saved: rfc3339_utc(SystemTime::now()),
+            source,
+            configs,
+            metadata,
+        };
+
+        let path = baseline_path(name, task, major, minor)?;
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir).map_err(Error::io(dir))?;
+        }
+        // via a value, which puts the sidecar's plain values before its tables
+        let text = toml::Value::try_from(&baseline)
+            .and_then(|value| toml::to_string(&value))
+            .expect("baselines are always representable as TOML");
+        write(&path, text).map_err(Error::io(&path))?;
+        println!("[INFO] saved {} baseline {:?} of {} configurations to {:?}",
+            task, name, baseline.configs.len(), path);
+    }
+    if searched.len() == tasks.len() {
+        return Err(Error::NoResults {
+            demo: (major, minor),
+            searched,
+        });
+    }
+    Ok(())
+}
+
+/// How to decide whether a difference from a baseline is significant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompareOptions {
+    /// Confidence required, eg. `0.95`.
+    pub confidence: f64,
+    /// Smallest relative change in median time worth flagging, eg. `0.02`.
+    pub threshold: f64,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        CompareOptions {
+            confidence: 0.95,
+            threshold: 0.02,
+        }
+    }
+}
+
+/// Outcome of comparing a configuration to its baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Verdict {
+    /// Significantly slower.
+    Regression,
+    /// Significantly faster.
+    Improvement,
+    /// No significant change.
+    Unchanged,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Verdict::Regression => "REGRESSION",
+            Verdict::Improvement => "improvement",
+            Verdict::Unchanged => "unchanged",
+        })
+    }
+}
+
+/// Comparison of a configuration's samples to its baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub baseline_median: Millis,
+    pub current_median: Millis,
+    /// Relative change in median time, positive if slower.
+    pub change: f64,
+    /// Mann-Whitney U test p-value.
+    pub p_value: f64,
+    pub verdict: Verdict,
+}
+
+impl Comparison {
+    /// Compare samples, or `None` if either is empty.
+    pub fn new(baseline: &[Millis], current: &[Millis], options: &CompareOptions) -> Option<Self> {
+        let sorted = |samples: &[Millis]| {
+            let mut ms: Vec<f64> = samples.iter().map(|time| time.0).collect();
+            ms.sort_by(f64::total_cmp);
+            ms
+        };
+        let (baseline, current) = (sorted(baseline), sorted(current));
+        let p_value = mann_whitney_p(&baseline, &current)?;
+        let baseline_median = percentile(&baseline, 50.0);
+        let current_median = percentile(&current, 50.0);
+        let change = match baseline_median {
+            m if m > 0.0 => current_median / m - 1.0,
+            _ => 0.0,
+        };
+        let significant = 1.0 - p_value >= options.confidence
+            && change.abs() >= options.threshold;
+        Some(Comparison {
+            baseline_median: Millis(baseline_median),
+            current_median: Millis(current_median),
+            change,
+            p_value,
+            verdict: match (significant, change > 0.0) {
+                (false, _) => Verdict::Unchanged,
+                (true, true) => Verdict::Regression,
+                (true, false) => Verdict::Improvement,
+            },
+        })
+    }
+
+    /// Confidence that the distributions differ, `1 - p_value`.
+    pub fn confidence(&self) -> f64 {
+        1.0 - self.p_value
+    }
+}
+
+/// Print a comparison as an aligned console line, labelled by configuration,
+/// padded to `width`.
+pub fn print_comparison(config: &str, comparison: &Comparison, width: usize) {
+    println!("{}{:width$}  {:>10} → {:>10}  {:>+7.1}%  {:>5.1}% confidence  {}",
+        INFO_INDENT, config,
+        comparison.baseline_median.to_string(),
+        comparison.current_median.to_string(),
+        comparison.change * 100.0, comparison.confidence() * 100.0,
+        comparison.verdict, width = width);
+}
+
+/// Row of a baseline comparison table.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CompareRow {
+    pub task: String,
+    pub config: String,
+    pub baseline_median_ms: Millis,
+    pub current_median_ms: Millis,
+    pub change_percent: f64,
+    pub p_value: f64,
+    pub confidence: f64,
+    pub verdict: Verdict,
+}
+
+/// `baseline compare` task.
+///
+/// Compares the latest results of each of `tasks` on the demo against the
+/// baseline `name`, configuration by configuration, skipping tasks without
+/// both. Fails if there was nothing to compare, or if anything regressed.
+pub fn compare(
+    tasks: &[&str],
+    name: &str,
+    major: u32,
+    minor: u32,
+    options: &CompareOptions,
+    mut table: TableWriter<CompareRow>,
+) -> Result<()> {
+    let mut searched = Vec::new();
+    let mut missing_results = Vec::new();
+    let mut compared = 0;
+    let mut regressed = Vec::new();
+    for &task in tasks {
+        let path = baseline_path(name, task, major, minor)?;
+        let baseline = match read_baseline(&path)? {
+            Some(baseline) => baseline,
+            None => {
+                searched.push(path);
+                continue;
+            },
+        };
+        let source = samples_path(task, major, minor)?;
+        let current = match read_samples(&source)? {
+            Some(current) => current,
+            None => {
+                println!("[WARN] {} baseline {:?} exists, but there are no \
+                    results in {:?} to compare", task, name, source);
+                missing_results.push(source);
+                continue;
+            },
+        };
+        compared += 1;
+
+        println!("[INFO] comparing {} against baseline {:?}, saved {}{}", task,
+            name, baseline.saved, baseline.git_commit()
+                .map(|commit| format!(" at commit {}", commit))
+                .unwrap_or_default());
+        println!();
+        let width = current.iter().map(|config| config.config.len()).max().unwrap_or(0);
+        for config in &current {
+            let comparison = baseline.configs.iter()
+                .find(|saved| saved.config == config.config)
+                .and_then(|saved| Comparison::new(
+                    &saved.samples_ms, &config.samples_ms, options));
+            let comparison = match comparison {
+                Some(comparison) => comparison,
+                None => {
+                    println!("{}{:width$}  not in baseline", INFO_INDENT,
+                        config.config, width = width);
+                    continue;
+                },
+            };
+            print_comparison(&config.config, &comparison, width);
+            if comparison.verdict == Verdict::Regression {
+                regressed.push(format!("{} {}", task, config.config));
+            }
+            table.write(CompareRow {
+                task: task.to_owned(),
+                config: config.config.clone(),
+                baseline_median_ms: comparison.baseline_median,
+                current_median_ms: comparison.current_median,
+                change_percent: comparison.change * 100.0,
+                p_value: comparison.p_value,
+                confidence: comparison.confidence(),
+                verdict: comparison.verdict,
+            })?;
+        }
+        for saved in &baseline.configs {
+            if !current.iter().any(|config| config.config == saved.config) {
+                println!("{}{:width$}  not in latest results",
+                    INFO_INDENT, saved.config, width = width);
+            }
+        }
+        println!();
+    }
+
+    if compared == 0 && !missing_results.is_empty() {
+        return Err(Error::NoResults {
+            demo: (major, minor),
+            searched: missing_results,
+        });
+    }
+    if compared == 0 {
+        return Err(Error::NoBaseline {
+            demo: (major, minor),
+            name: name.to_owned(),
+            searched,
+        });
+    }
+    if !regressed.is_empty() {
+        return Err(Error::Regressions {
+            demo: (major, minor),
+            regressed,
+        });
+    }
+    println!("[INFO] no significant regressions");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASELINE: [f64; 5] = [100.0, 101.0, 102.0, 103.0, 104.0];
+
+    fn millis(ms: &[f64]) -> Vec<Millis> {
+        ms.iter().copied().map(Millis).collect()
+    }
+
+    fn scaled(factor: f64) -> Vec<Millis> {
+        millis(&BASELINE.iter().map(|ms| ms * factor).collect::<Vec<_>>())
+    }
+
+    fn verdict(current: &[Millis], options: &CompareOptions) -> Verdict {
+        Comparison::new(&millis(&BASELINE), current, options).unwrap().verdict
+    }
+
+    #[test]
+    fn slower_is_regression_and_faster_improvement() {
+        let options = CompareOptions::default();
+        let slower = Comparison::new(&millis(&BASELINE), &scaled(1.1), &options).unwrap();
+        assert_eq!(slower.verdict, Verdict::Regression);
+        assert!((slower.change - 0.1).abs() < 1e-12);
+        assert!((slower.p_value - 0.0122).abs() < 1e-4);
+        assert_eq!(slower.baseline_median, Millis(102.0));
+        assert_eq!(verdict(&scaled(0.9), &options), Verdict::Improvement);
+        assert_eq!(verdict(&millis(&BASELINE), &options), Verdict::Unchanged);
+    }
+
+    #[test]
+    fn confidence_boundary() {
+        let current = scaled(1.1);
+        let comparison = Comparison::new(
+            &millis(&BASELINE), &current, &CompareOptions::default()).unwrap();
+        let at = CompareOptions {
+            confidence: comparison.confidence(),
+            threshold: 0.0,
+        };
+        assert_eq!(verdict(&current, &at), Verdict::Regression);
+        let above = CompareOptions {
+            confidence: comparison.confidence() + 1e-9,
+            ..at
+        };
+        assert_eq!(verdict(&current, &above), Verdict::Unchanged);
+    }
+
+    #[test]
+    fn threshold_boundary() {
+        for (factor, significant) in [(1.1, Verdict::Regression), (0.9, Verdict::Improvement)] {
+            let current = scaled(factor);
+            let comparison = Comparison::new(
+                &millis(&BASELINE), &current, &CompareOptions::default()).unwrap();
+            let at = CompareOptions {
+                confidence: 0.95,
+                threshold: comparison.change.abs(),
+            };
+            assert_eq!(verdict(&current, &at), significant);
+            let above = CompareOptions {
+                threshold: comparison.change.abs() + 1e-9,
+                ..at
+            };
+            assert_eq!(verdict(&current, &above), Verdict::Unchanged);
+        }
+    }
+
+    #[test]
+    fn empty_samples() {
+        let options = CompareOptions::default();
+        assert_eq!(Comparison::new(&[], &millis(&BASELINE), &options), None);
+        assert_eq!(Comparison::new(&millis(&BASELINE), &[], &options), None);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{
+    navigate::repo_dir,
+    compile::Profile,
+    error::{Error, Result},
+};
+use std::{
+    path::{Path, PathBuf},
+    hash::{Hash, Hasher},
+    ffi::OsStr,
+    fs::{
+        self,
+        create_dir,
+        create_dir_all,
+        remove_dir_all,
+    },
+    fmt::{self, Display, Formatter},
+    sync::atomic::{AtomicU64, Ordering},
+    io::ErrorKind,
+    process,
+    env,
+};
+use byte_unit::Byte;
+
+/// Env var which may override the cache directory location.
+pub const CACHE_ENV_VAR: &str = "CS39_CACHE";
+
+/// Root of the cs39-managed cache.
+///
+/// `CS39_CACHE` if set, made absolute as builds run in other directories,
+/// otherwise `.cs39` in the repo root.
+pub fn cache_dir() -> Result<PathBuf> {
+    match env::var_os(CACHE_ENV_VAR) {
+        Some(path) => {
+            let cwd = env::current_dir().map_err(Error::io("."))?;
+            Ok(cwd.join(path))
+        },
+        None => Ok(repo_dir()?.join(".cs39")),
+    }
+}
+
+/// Allocate the build directory for a demo, profile, and build key.
+///
+/// Laid out as `build/<demo dir name>/<profile name>/<build key>`, so that
+/// builds differing in sources, flags, or defines don't collide.
+pub fn build_dir<P: AsRef<Path>>(
+    demo: P, 
+    profile: &Profile, 
+    key: u64
+) -> Result<PathBuf> {
+    let dir = cache_subdir("build", demo.as_ref(), profile, key)?;
+    create_dir_all(&dir).map_err(Error::io(&dir))?;
+    Ok(dir)
+}
+
+/// Allocate the object file directory for a demo, profile, and object key.
+///
+/// Laid out as `obj/<demo dir name>/<profile name>/<object key>`, holding a
+/// directory of cached objects per source file.
+pub fn object_dir<P: AsRef<Path>>(
+    demo: P, 
+    profile: &Profile, 
+    key: u64
+) -> Result<PathBuf> {
+    let dir = cache_subdir("obj", demo.as_ref(), profile, key)?;
+    create_dir_all(&dir).map_err(Error::io(&dir))?;
+    Ok(dir)
+}
+
+/// Allocate a new, empty directory to stage modified code in.
+///
+/// Named `staging/<pid>-<n>`, and never reused, even if an old one was left
+/// behind.
+pub fn staging_dir() -> Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    
+    let root = cache_dir()?.join("staging");
+    create_dir_all(&root).map_err(Error::io(&root))?;
+    loop {
+        let n = NEXT.fetch_add(1, Ordering::SeqCst);
+        let dir = root.join(format!("{}-{}", process::id(), n));
+        match create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(Error::io(dir)(e)),
+        }
+    }
+}
+
+/// Path of a per-demo, per-profile cache directory.
+fn cache_subdir(kind: &str, demo: &Path, profile: &Profile, key: u64) -> Result<PathBuf> {
+    let demo_name = demo.file_name().unwrap_or_else(|| OsStr::new("demo"));
+    Ok(cache_dir()?
+        .join(kind)
+        .join(demo_name)
+        .join(slug(&profile.name))
+        .join(format!("{:016x}", key)))
+}
+
+/// Number and total size of some cache entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DiskUsage {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl DiskUsage {
+    fn add(&mut self, other: DiskUsage) {
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+    }
+}
+
+impl Display for DiskUsage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.entries, Byte::from_bytes(self.bytes as u128)
+            .get_appropriate_unit(true)
+            .format(1))
+    }
+}
+
+/// Disk usage of each part of the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CacheUsage {
+    /// Linked builds.
+    pub builds: DiskUsage,
+    /// Object files.
+    pub objects: DiskUsage,
+    /// Staging directories of modified code, left by failed builds or 
+    /// `--keep-build`.
+    pub staging: DiskUsage,
+}
+
+impl CacheUsage {
+    /// Total size.
+    pub fn bytes(&self) -> u64 {
+        self.builds.bytes + self.objects.bytes + self.staging.bytes
+    }
+}
+
+/// Measure the disk usage of the cache.
+pub fn cache_usage() -> Result<CacheUsage> {
+    let root = cache_dir()?;
+    Ok(CacheUsage {
+        builds: entries_usage(&root.join("build"), 3, is_any),
+        objects: entries_usage(&root.join("obj"), 5, is_object),
+        staging: entries_usage(&root.join("staging"), 1, is_any),
+    })
+}
+
+/// Delete the staging directories, and if `all`, every build and object
+/// file. Returns what was deleted.
+///
+/// Also deletes the `tmp/rng-*` directories older versions of cs39 left in
+/// the repo. Should not be run while other cs39 tasks are building.
+pub fn clean(all: bool) -> Result<CacheUsage> {
+    let root = cache_dir()?;
+    let mut removed = CacheUsage::default();
+    let mut parts = vec![("staging", 1, is_any as fn(&Path) -> bool, &mut removed.staging)];
+    if all {
+        parts.push(("build", 3, is_any, &mut removed.builds));
+        parts.push(("obj", 5, is_object, &mut removed.objects));
+    }
+    for (name, depth, count, usage) in parts {
+        let dir = root.join(name);
+        if !dir.exists() {
+            continue;
+        }
+        *usage = entries_usage(&dir, depth, count);
+        remove_dir_all(&dir).map_err(Error::io(&dir))?;
+    }
+    
+    let legacy = repo_dir()?.join("tmp");
+    let legacy_dirs = fs::read_dir(&legacy).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && path.file_name()
+            .and_then(OsStr::to_str)
+            .map(|name| name.starts_with("rng-"))
+            .unwrap_or(false));
+    for dir in legacy_dirs {
+        removed.staging.add(DiskUsage {
+            entries: 1,
+            bytes: tree_bytes(&dir),
+        });
+        remove_dir_all(&dir).map_err(Error::io(&dir))?;
+    }
+    Ok(removed)
+}
+
+fn is_any(_: &Path) -> bool {
+    true
+}
+
+fn is_object(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("o"))
+}
+
+/// Disk usage of the entries `depth` levels below a directory, counting
+/// those matching `count`.
+fn entries_usage(dir: &Path, depth: u32, count: fn(&Path) -> bool) -> DiskUsage {
+    let mut usage = DiskUsage::default();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return usage,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if depth > 1 {
+            usage.add(entries_usage(&path, depth - 1, count));
+        } else {
+            usage.add(DiskUsage {
+                entries: count(&path) as u64,
+                bytes: tree_bytes(&path),
+            });
+        }
+    }
+    usage
+}
+
+/// Total size of the files in a directory tree.
+fn tree_bytes(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::read_dir(path)
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| tree_bytes(&entry.path()))
+                .sum())
+            .unwrap_or(0),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Make a string safe for use as a file name.
+pub fn slug(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c,
+            '-' | '_' | '+' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Hash which doesn't change between runs or builds of cs39.
+pub fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 64-bit FNV-1a hasher.
+///
+/// Unlike `DefaultHasher`, its output is specified, so it's suitable for
+/// naming things on disk.
+#[derive(Debug, Clone)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    pub fn new() -> Self {
+        StableHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,714 @@
+use crate::output::{Indent, INFO_INDENT};
+use std::{
+    fmt::{self, Display, Formatter},
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+/// Kind of command-line argument.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ArgKind {
+    /// Bare value, matched by position.
+    Positional,
+    /// `--name`, takes no value.
+    Flag,
+    /// `--name VALUE`.
+    Option,
+}
+
+/// Definition of a command-line argument.
+#[derive(Debug, Clone, Copy)]
+pub struct ArgDef {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    /// Placeholder shown in usage strings, for arguments that take values.
+    pub value_name: &'static str,
+    pub required: bool,
+    pub help: &'static str,
+}
+
+impl ArgDef {
+    pub const fn positional(name: &'static str, help: &'static str) -> Self {
+        ArgDef {
+            name,
+            kind: ArgKind::Positional,
+            value_name: name,
+            required: true,
+            help,
+        }
+    }
+
+    pub const fn flag(name: &'static str, help: &'static str) -> Self {
+        ArgDef {
+            name,
+            kind: ArgKind::Flag,
+            value_name: "",
+            required: false,
+            help,
+        }
+    }
+
+    pub const fn option(
+        name: &'static str,
+        value_name: &'static str,
+        help: &'static str,
+    ) -> Self {
+        ArgDef {
+            name,
+            kind: ArgKind::Option,
+            value_name,
+            required: false,
+            help,
+        }
+    }
+
+    /// Usage string fragment, eg. `MAJOR` or `[--threads N]`.
+    fn usage(&self) -> String {
+        let core = match self.kind {
+            ArgKind::Positional => self.value_name.to_owned(),
+            ArgKind::Flag => format!("--{}", self.name),
+            ArgKind::Option => format!("--{} {}", self.name, self.value_name),
+        };
+        if self.required {
+            core
+        } else {
+            format!("[{}]", core)
+        }
+    }
+}
+
+/// Definition of a subcommand.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandDef {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub args: &'static [ArgDef],
+}
+
+impl CommandDef {
+    /// One-line usage string.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("cs39 {}", self.name);
+        for arg in self.args {
+            usage.push(' ');
+            usage.push_str(&arg.usage());
+        }
+        usage
+    }
+
+    fn positionals(&self) -> impl Iterator<Item=&'static ArgDef> {
+        self.args.iter().filter(|arg| arg.kind == ArgKind::Positional)
+    }
+
+    fn named(&self, name: &str) -> Option<&'static ArgDef> {
+        self.args.iter()
+            .find(|arg| arg.kind != ArgKind::Positional && arg.name == name)
+    }
+}
+
+impl PartialEq for CommandDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Display for CommandDef {
+    /// Detailed help page.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "USAGE:")?;
+        writeln!(f, "{}", Indent(INFO_INDENT, self.usage()))?;
+        writeln!(f)?;
+        writeln!(f, "{}", Indent(INFO_INDENT, self.about))?;
+        if !self.args.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "ARGS:")?;
+            let width = self.args.iter()
+                .map(|arg| arg.usage().len())
+                .max()
+                .unwrap_or(0);
+            for arg in self.args {
+                writeln!(f, "{}{:width$}  {}",
+                    INFO_INDENT, arg.usage(), arg.help, width = width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const MAJOR: ArgDef = ArgDef::positional(
+    "MAJOR", "major demo number");
+const MINOR: ArgDef = ArgDef::positional(
+    "MINOR", "minor demo number");
+const COMPILER: ArgDef = ArgDef::option(
+    "compiler", "NAME", "compiler profile to use, eg. gcc, gcc-9, clang++, gcc-mkl");
+const JOBS: ArgDef = ArgDef::option(
+    "jobs", "N", "number of builds to run at once (default logical CPUs)");
+const INJECT_DEFINES: ArgDef = ArgDef::flag(
+    "inject-defines", "override #ifndef-guarded defines with -D flags \
+                       instead of rewriting the source");
+const KEEP_BUILD: ArgDef = ArgDef::flag(
+    "keep-build", "keep the modified source of each build, which is \
+                   otherwise deleted once built");
+const PERF: ArgDef = ArgDef::flag(
+    "perf", "count cycles, instructions, cache misses, LLC loads and load \
+             misses, and branch misses in each run (Linux only)");
+const WARMUP: ArgDef = ArgDef::option(
+    "warmup", "N", "iterations at the start of each run to leave out of the \
+                    statistics (default 1)");
+const REPEAT: ArgDef = ArgDef::option(
+    "repeat", "N", "run each configuration N times, as separate processes, \
+                    and report run-to-run variation (default 1)");
+const INTERLEAVE: ArgDef = ArgDef::flag(
+    "interleave", "with --repeat, run every configuration once per round \
+                   rather than each one's runs back to back");
+const MINOR_OR_ALL: ArgDef = ArgDef::positional(
+    "MINOR", "minor demo number, or `all` for every demo in the major");
+
+/// All subcommands of the CLI.
+pub const COMMANDS: &[CommandDef] = &[
+    CommandDef {
+        name: "run",
+        about: "compile and run a demo",
+        args: &[MAJOR, MINOR, COMPILER],
+    },
+    CommandDef {
+        name: "list",
+        about: "list available demos",
+        args: &[],
+    },
+    CommandDef {
+        name: "stat",
+        about: "print information about the CPU, available compilers, and \
+                the build cache",
+        args: &[],
+    },
+    CommandDef {
+        name: "clean",
+        about: "delete leftover copies of modified demo source from the \
+                build cache, and report its disk usage",
+        args: &[
+            ArgDef::flag("all", "also delete every cached build and object \
+                                 file"),
+        ],
+    },
+    CommandDef {
+        name: "cpu_test",
+        about: "benchmark a demo with each thread count in a range, \
+                writing a CSV file",
+        args: &[
+            MAJOR,
+            MINOR_OR_ALL,
+            COMPILER,
+            JOBS,
+            ArgDef::option("min-threads", "N",
+                "smallest thread count to test (default 1)"),
+            ArgDef::option("max-threads", "N",
+                "largest thread count to test (default logical CPUs)"),
+            REPEAT,
+            INTERLEAVE,
+            WARMUP,
+            PERF,
+        ],
+    },
+    CommandDef {
+        name: "size_test",
+        about: "benchmark a demo with a range of grid sizes, scaling \
+                XDIM/YDIM/ZDIM/etc. together, writing a CSV file",
+        args: &[
+            MAJOR,
+            MINOR_OR_ALL,
+            COMPILER,
+            JOBS,
+            INJECT_DEFINES,
+            KEEP_BUILD,
+            REPEAT,
+            INTERLEAVE,
+            WARMUP,
+            PERF,
+        ],
+    },
+    CommandDef {
+        name: "flag_test",
+        about: "benchmark a demo built with each combination of compiler \
+                profile and extra compiler flags, writing a CSV file",
+        args: &[
+            MAJOR,
+            MINOR_OR_ALL,
+            ArgDef::option("profiles", "NAMES",
+                "comma-separated compiler profiles (default the selected \
+                 compiler)"),
+            COMPILER,
+            ArgDef::option("flags", "SETS",
+                "semicolon-separated sets of flags to append, eg. \
+                 \"-O2;-O3 -march=native\" (default -O2, -O3, -Ofast, \
+                 -O3 -march=native, -O3 -funroll-loops)"),
+            ArgDef::option("threads", "N",
+                "OMP_NUM_THREADS for the demo (default unset)"),
+            JOBS,
+            WARMUP,
+            PERF,
+        ],
+    },
+    CommandDef {
+        name: "sweep",
+        about: "benchmark a demo with each combination of values for some \
+                #define directives, writing a CSV file, or list the \
+                directives if no --params are given",
+        args: &[
+            MAJOR,
+            MINOR_OR_ALL,
+            ArgDef::option("params", "SPECS",
+                "semicolon-separated NAME=VALUES, where VALUES are \
+                 comma-separated literals, `defined`, `undef`, or integer \
+                 ranges A..B, A..B:STEP, or A..B*FACTOR, eg. \
+                 \"BLOCK_SIZE=8..64*2;CHANGES=true,false\""),
+            COMPILER,
+            ArgDef::option("threads", "N",
+                "OMP_NUM_THREADS for the demo (default unset)"),
+            JOBS,
+            INJECT_DEFINES,
+            KEEP_BUILD,
+            WARMUP,
+            PERF,
+        ],
+    },
+    CommandDef {
+        name: "compare",
+        about: "benchmark several minor versions of a demo under identical \
+                conditions and rank them by speedup over a reference \
+                version, writing a CSV file",
+        args: &[
+            MAJOR,
+            ArgDef::option("minors", "LIST",
+                "comma-separated minor versions to compare (default every \
+                 one in the major)"),
+            ArgDef::option("reference", "MINOR",
+                "minor version speedups are relative to (default the first \
+                 compared)"),
+            COMPILER,
+            ArgDef::option("threads", "N",
+                "OMP_NUM_THREADS for the demo (default unset)"),
+            JOBS,
+            REPEAT,
+            INTERLEAVE,
+            WARMUP,
+            PERF,
+        ],
+    },
+    CommandDef {
+        name: "diff",
+        about: "show a unified diff of the source files of two minor \
+                versions of a demo, including added and removed files",
+        args: &[
+            MAJOR,
+            ArgDef::positional("A", "minor version to diff from"),
+            ArgDef::positional("B", "minor version to diff to"),
+            ArgDef::option("context", "N",
+                "lines of context around each change (default 3)"),
+            ArgDef::flag("timing",
+                "also compare the latest cpu_test, size_test, and compare \
+                 results of both versions"),
+        ],
+    },
+    CommandDef {
+        name: "kernel_sum_test",
+        about: "run a demo and sum up the time reported for each kernel",
+        args: &[
+            ArgDef::flag("multithreaded",
+                "use every logical CPU instead of a single thread"),
+            ArgDef::option("major", "N", "major demo number (default 1)"),
+            ArgDef::option("minor", "N", "minor demo number (default 2)"),
+            COMPILER,
+        ],
+    },
+    CommandDef {
+        name: "baseline",
+        about: "save the latest cpu_test and size_test results of a demo as \
+                a baseline, or compare the latest results against one, \
+                failing if any configuration got significantly slower; \
+                baselines are saved to `baselines/` in the repo root, to be \
+                committed",
+        args: &[
+            ArgDef::positional("ACTION", "`save` or `compare`"),
+            MAJOR,
+            MINOR_OR_ALL,
+            ArgDef::option("name", "NAME",
+                "name of the baseline, to keep several (default `default`)"),
+            ArgDef::option("task", "TASK",
+                "only use cpu_test or size_test results (default both)"),
+            ArgDef::option("confidence", "PERCENT",
+                "confidence needed to flag a change (default 95)"),
+            ArgDef::option("threshold", "PERCENT",
+                "smallest change in median time to flag (default 2)"),
+        ],
+    },
+    CommandDef {
+        name: "reinstall",
+        about: "recompile and reinstall the cs39 CLI from source",
+        args: &[],
+    },
+    CommandDef {
+        name: "help",
+        about: "print help, for the whole CLI or for one subcommand",
+        args: &[ArgDef {
+            required: false,
+            ..ArgDef::positional("SUBCOMMAND", "subcommand to describe")
+        }],
+    },
+];
+
+/// Look up a subcommand definition by name.
+pub fn find_command(name: &str) -> Option<&'static CommandDef> {
+    COMMANDS.iter().find(|cmd| cmd.name == name)
+}
+
+/// Top-level help page.
+pub struct Manual;
+
+impl Display for Manual {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Phoenix Kahlo's CLI for sifakis/CS639S20_Demos")?;
+        writeln!(f)?;
+        writeln!(f, "USAGE:")?;
+        for cmd in COMMANDS {
+            writeln!(f, "{}", Indent(INFO_INDENT, cmd.usage()))?;
+            writeln!(f, "{}", Indent(INFO_INDENT, Indent(INFO_INDENT, cmd.about)))?;
+            writeln!(f)?;
+        }
+        writeln!(f, "EXAMPLE:")?;
+        writeln!(f, "{}", Indent(INFO_INDENT, "cs39 run 0 2"))?;
+        writeln!(f, "{}", Indent(INFO_INDENT, Indent(INFO_INDENT,
+            "compile and run LaplacianStencil_0_2")))?;
+        writeln!(f)?;
+        write!(f, "{}", Indent(INFO_INDENT,
+            "see `cs39 help SUBCOMMAND` for subcommand details"))
+    }
+}
+
+/// Command-line parsing error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    NoSubcommand,
+    UnknownSubcommand(String),
+    MissingArg {
+        command: &'static CommandDef,
+        arg: &'static str,
+    },
+    UnexpectedArg {
+        command: &'static CommandDef,
+        arg: String,
+    },
+    MissingValue {
+        command: &'static CommandDef,
+        arg: &'static str,
+    },
+    InvalidValue {
+        command: &'static CommandDef,
+        arg: &'static str,
+        value: String,
+    },
+}
+
+impl CliError {
+    /// Subcommand the error occurred in, if known.
+    pub fn command(&self) -> Option<&'static CommandDef> {
+        match *self {
+            CliError::NoSubcommand | CliError::UnknownSubcommand(_) => None,
+            CliError::MissingArg { command, .. }
+            | CliError::UnexpectedArg { command, .. }
+            | CliError::MissingValue { command, .. }
+            | CliError::InvalidValue { command, .. } => Some(command),
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CliError::NoSubcommand =>
+                write!(f, "no subcommand given"),
+            CliError::UnknownSubcommand(name) =>
+                write!(f, "unknown subcommand {:?}", name),
+            CliError::MissingArg { command, arg } =>
+                write!(f, "{} is missing required argument {}",
+                    command.name, arg),
+            CliError::UnexpectedArg { command, arg } =>
+                write!(f, "{} does not accept argument {:?}",
+                    command.name, arg),
+            CliError::MissingValue { command, arg } =>
+                write!(f, "{} option --{} requires a value",
+                    command.name, arg),
+            CliError::InvalidValue { command, arg, value } =>
+                write!(f, "{} got invalid value {:?} for {}",
+                    command.name, value, arg),
+        }
+    }
+}
+
+/// Parsed command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Invocation {
+    /// Print help, optionally for a specific subcommand.
+    Help(Option<&'static CommandDef>),
+    /// Run a subcommand.
+    Command(Matches),
+}
+
+/// Arguments matched against a subcommand definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matches {
+    pub command: &'static CommandDef,
+    values: HashMap<&'static str, String>,
+    flags: HashSet<&'static str>,
+}
+
+impl Matches {
+    /// Whether a flag was passed.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// Raw string value of a positional or option, if present.
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Parse the value of a positional or option, if present.
+    pub fn value<T: FromStr>(&self, name: &str) -> Result<Option<T>, CliError> {
+        let arg = self.def(name);
+        match self.values.get(name) {
+            None => Ok(None),
+            Some(value) => value.parse()
+                .map(Some)
+                .map_err(|_| CliError::InvalidValue {
+                    command: self.command,
+                    arg: arg.name,
+                    value: value.clone(),
+                }),
+        }
+    }
+
+    /// Parse the value of a positional or option, erroring if absent.
+    pub fn required<T: FromStr>(&self, name: &str) -> Result<T, CliError> {
+        let arg = self.def(name);
+        self.value(name)?
+            .ok_or(CliError::MissingArg {
+                command: self.command,
+                arg: arg.name,
+            })
+    }
+
+    /// Build an invalid value error for an argument of this command.
+    pub fn invalid(&self, name: &str) -> CliError {
+        let arg = self.def(name);
+        CliError::InvalidValue {
+            command: self.command,
+            arg: arg.name,
+            value: self.raw(name).unwrap_or("").to_owned(),
+        }
+    }
+
+    fn def(&self, name: &str) -> &'static ArgDef {
+        self.command.args.iter()
+            .find(|arg| arg.name == name)
+            .unwrap_or_else(|| panic!(
+                "argument {:?} not defined for {}", name, self.command.name))
+    }
+}
+
+fn is_help(arg: &str) -> bool {
+    arg == "help" || arg == "--help" || arg == "-h"
+}
+
+/// Parse command-line arguments, excluding the program name.
+pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Invocation, CliError> {
+    let mut args = args.iter().map(AsRef::as_ref);
+
+    let name = args.next().ok_or(CliError::NoSubcommand)?;
+    if is_help(name) {
+        return match args.next() {
+            None => Ok(Invocation::Help(None)),
+            Some(sub) => find_command(sub)
+                .map(|cmd| Invocation::Help(Some(cmd)))
+                .ok_or_else(|| CliError::UnknownSubcommand(sub.to_owned())),
+        };
+    }
+    // legacy spelling from the original manual
+    let name = match name {
+        "--list" => "list",
+        name => name,
+    };
+    let command = find_command(name)
+        .ok_or_else(|| CliError::UnknownSubcommand(name.to_owned()))?;
+
+    let mut matches = Matches {
+        command,
+        values: HashMap::new(),
+        flags: HashSet::new(),
+    };
+    let mut positionals = command.positionals();
+
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(Invocation::Help(Some(command)));
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (long, inline) = match long.find('=') {
+                Some(i) => (&long[..i], Some(&long[i + 1..])),
+                None => (long, None),
+            };
+            let def = command.named(long)
+                .ok_or_else(|| CliError::UnexpectedArg {
+                    command,
+                    arg: arg.to_owned(),
+                })?;
+            match def.kind {
+                ArgKind::Flag => {
+                    if inline.is_some() {
+                        return Err(CliError::UnexpectedArg {
+                            command,
+                            arg: arg.to_owned(),
+                        });
+                    }
+                    matches.flags.insert(def.name);
+                },
+                ArgKind::Option => {
+                    let value = inline
+                        .or_else(|| args.next())
+                        .ok_or(CliError::MissingValue {
+                            command,
+                            arg: def.name,
+                        })?;
+                    matches.values.insert(def.name, value.to_owned());
+                },
+                ArgKind::Positional => unreachable!(),
+            }
+        } else {
+            let def = positionals.next()
+                .ok_or_else(|| CliError::UnexpectedArg {
+                    command,
+                    arg: arg.to_owned(),
+                })?;
+            matches.values.insert(def.name, arg.to_owned());
+        }
+    }
+
+    for def in positionals {
+        if def.required {
+            return Err(CliError::MissingArg {
+                command,
+                arg: def.name,
+            });
+        }
+    }
+
+    Ok(Invocation::Command(matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(args: &[&str]) -> Matches {
+        match parse(args) {
+            Ok(Invocation::Command(matches)) => matches,
+            other => panic!("expected a command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn positionals() {
+        let m = matches(&["run", "0", "3"]);
+        assert_eq!(m.command.name, "run");
+        assert_eq!(m.value::<u32>("MAJOR"), Ok(Some(0)));
+        assert_eq!(m.value::<u32>("MINOR"), Ok(Some(3)));
+        assert_eq!(m.raw("compiler"), None);
+    }
+
+    #[test]
+    fn missing_positional() {
+        let err = parse(&["run", "0"]).unwrap_err();
+        assert_eq!(err, CliError::MissingArg {
+            command: find_command("run").unwrap(),
+            arg: "MINOR",
+        });
+    }
+
+    #[test]
+    fn extra_positional() {
+        assert!(matches!(parse(&["run", "0", "3", "4"]),
+            Err(CliError::UnexpectedArg { .. })));
+    }
+
+    #[test]
+    fn options_separate_and_inline() {
+        let m = matches(&["cpu_test", "0", "3", "--max-threads", "4", "--compiler=clang++"]);
+        assert_eq!(m.value::<u32>("max-threads"), Ok(Some(4)));
+        assert_eq!(m.raw("compiler"), Some("clang++"));
+        assert_eq!(m.value::<u32>("min-threads"), Ok(None));
+    }
+
+    #[test]
+    fn inline_value_may_contain_equals() {
+        let m = matches(&["sweep", "0", "3", "--params=BLOCK=1,2;N=3"]);
+        assert_eq!(m.raw("params"), Some("BLOCK=1,2;N=3"));
+    }
+
+    #[test]
+    fn flags() {
+        let m = matches(&["cpu_test", "0", "all", "--perf"]);
+        assert!(m.flag("perf"));
+        assert!(!m.flag("interleave"));
+        assert!(matches!(parse(&["cpu_test", "0", "3", "--perf=yes"]),
+            Err(CliError::UnexpectedArg { .. })));
+    }
+
+    #[test]
+    fn option_missing_value() {
+        let err = parse(&["cpu_test", "0", "3", "--max-threads"]).unwrap_err();
+        assert_eq!(err, CliError::MissingValue {
+            command: find_command("cpu_test").unwrap(),
+            arg: "max-threads",
+        });
+    }
+
+    #[test]
+    fn invalid_value() {
+        let m = matches(&["cpu_test", "0", "3", "--max-threads", "four"]);
+        assert!(matches!(m.value::<u32>("max-threads"),
+            Err(CliError::InvalidValue { arg: "max-threads", .. })));
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let err = parse(&["run", "0", "3", "--verbose"]).unwrap_err();
+        assert_eq!(err, CliError::UnexpectedArg {
+            command: find_command("run").unwrap(),
+            arg: "--verbose".to_owned(),
+        });
+        assert_eq!(err.command().map(|command| command.name), Some("run"));
+    }
+
+    #[test]
+    fn unknown_subcommand() {
+        assert_eq!(parse(&["frobnicate"]).unwrap_err(),
+            CliError::UnknownSubcommand("frobnicate".to_owned()));
+        assert_eq!(parse(&["help", "frobnicate"]).unwrap_err(),
+            CliError::UnknownSubcommand("frobnicate".to_owned()));
+        assert_eq!(parse::<&str>(&[]).unwrap_err(), CliError::NoSubcommand);
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(parse(&["help"]), Ok(Invocation::Help(None)));
+        assert_eq!(parse(&["--help"]), Ok(Invocation::Help(None)));
+        assert_eq!(parse(&["help", "cpu_test"]),
+            Ok(Invocation::Help(find_command("cpu_test"))));
+        assert_eq!(parse(&["size_test", "--help"]),
+            Ok(Invocation::Help(find_command("size_test"))));
+    }
+
+    #[test]
+    fn legacy_list() {
+        assert_eq!(matches(&["--list"]).command.name, "list");
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    navigate::DemoLookup,
+    compile::{
+        build_all,
+        BuildJob,
+        BuildOptions,
+        Profile,
+    },
+    output::{
+        INFO_INDENT,
+        TableWriter,
+    },
+    quant::{
+        benchmark,
+        repeat_runs,
+        run_suffix,
+        BenchOptions,
+        Measurement,
+        ResourceUsage,
+    },
+    perf::PerfCounts,
+    stats::{RunSpread, SampleRow, Summary},
+    units::Millis,
+    error::{Error, Result},
+};
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+
+/// Header row of a compare table, with hardware event columns if `perf`.
+pub fn header(perf: bool) -> Vec<String> {
+    ["Rank", "Major", "Minor", "Demo", "BestTimeMs", "SpeedupVsReference"].iter()
+        .chain(Summary::COLUMNS)
+        .chain(RunSpread::COLUMNS)
+        .chain(ResourceUsage::COLUMNS)
+        .chain(if perf { PerfCounts::COLUMNS } else { &[] })
+        .map(|&column| column.to_owned())
+        .collect()
+}
+
+/// Row of a compare table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompareRow {
+    /// Counting from 1 for the fastest.
+    pub rank: usize,
+    pub major: u32,
+    pub minor: u32,
+    /// Name of the demo directory.
+    pub demo: String,
+    pub best_time_ms: Millis,
+    /// Reference best time over this best time, so above 1 if faster. `None`
+    /// if the reference failed.
+    pub speedup: Option<f64>,
+    pub summary: Summary,
+    /// `Some` if combined from several runs.
+    pub spread: Option<RunSpread>,
+    pub usage: ResourceUsage,
+    pub counts: Option<PerfCounts>,
+}
+
+impl Serialize for CompareRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        seq.serialize_element(&self.rank)?;
+        seq.serialize_element(&self.major)?;
+        seq.serialize_element(&self.minor)?;
+        seq.serialize_element(&self.demo)?;
+        seq.serialize_element(&self.best_time_ms)?;
+        seq.serialize_element(&self.speedup)?;
+        self.summary.serialize_columns(&mut seq)?;
+        RunSpread::serialize_columns(self.spread.as_ref(), &mut seq)?;
+        self.usage.serialize_columns(&mut seq)?;
+        if let Some(counts) = &self.counts {
+            counts.serialize_columns(&mut seq)?;
+        }
+        seq.end()
+    }
+}
+
+/// `compare` task.
+///
+/// Every minor version in `minors` is built with the same profile first, up
+/// to `options.jobs` at a time, then benchmarked under the same `bench`
+/// options, `bench.repeat` times each. They're ranked by best time, with
+/// speedups relative to minor version `reference`, and written to `table` in
+/// rank order. Versions which fail to build or run are reported and skipped,
+/// and the task fails at the end if any did. Every iteration time is written
+/// to `samples`, labelled by minor version.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    lookup: &DemoLookup,
+    profile: &Profile,
+    major: u32,
+    minors: &[u32],
+    reference: u32,
+    options: &BuildOptions,
+    bench: &BenchOptions,
+    mut table: TableWriter<CompareRow>,
+    mut samples: TableWriter<SampleRow>,
+) -> Result<()> {
+    let jobs = minors.iter()
+        .map(|&minor| BuildJob::new(lookup, profile, major, minor))
+        .collect::<Result<Vec<_>>>()?;
+    let names: Vec<String> = jobs.iter()
+        .map(|job| job.demo_path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("{}_{}", major, job.demo.1)))
+        .collect();
+
+    println!("[INFO] comparing {} versions of major {}:", minors.len(), major);
+    for (&minor, name) in minors.iter().zip(&names) {
+        let marker = if minor == reference { " (reference)" } else { "" };
+        println!("{} • {}{}", INFO_INDENT, name, marker);
+    }
+    println!();
+
+    let builds = build_all(&jobs, options);
+    println!();
+
+    let mut failed: Vec<(u32, u32)> = Vec::new();
+    let mut built = Vec::new();
+    for (i, build) in builds.into_iter().enumerate() {
+        match build {
+            Ok(compiled) => built.push((i, compiled)),
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                failed.push((major, minors[i]));
+            },
+        }
+    }
+
+    let mut results: Vec<Option<Measurement>> = vec![None; minors.len()];
+    repeat_runs(built.len(), bench, |i, run| {
+        let (n, compiled) = &built[i];
+        println!("[INFO] benchmarking {}{}", names[*n], run_suffix(run, bench));
+        let measurement = benchmark(compiled, major, minors[*n], bench)?;
+        for row in measurement.sample_rows(&minors[*n].to_string(), run) {
+            samples.write(row)?;
+        }
+        if bench.repeat > 1 {
+            println!();
+        }
+        Ok(measurement)
+    }, |i, measurement| {
+        let n = built[i].0;
+        let measurement = match measurement {
+            Ok(measurement) => measurement,
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                println!();
+                failed.push((major, minors[n]));
+                return Ok(());
+            },
+        };
+        if let Some(spread) = &measurement.spread {
+            println!("[INFO] {}, {}", names[n], spread);
+        }
+        println!("[INFO] best time = {}", Millis::from(measurement.min_time));
+        println!();
+        results[n] = Some(measurement);
+        Ok(())
+    })?;
+    failed.sort_unstable();
+
+    let reference = minors.iter().position(|&minor| minor == reference);
+    let reference_time = reference
+        .and_then(|n| results[n].as_ref())
+        .map(|measurement| measurement.min_time);
+    let mut ranked: Vec<(usize, Measurement)> = results.into_iter()
+        .enumerate()
+        .filter_map(|(n, result)| result.map(|measurement| (n, measurement)))
+        .collect();
+    ranked.sort_by_key(|(_, measurement)| measurement.min_time);
+
+    if !ranked.is_empty() {
+        match (reference, reference_time) {
+            (Some(n), Some(_)) => println!("[INFO] ranking, relative to {}:", names[n]),
+            _ => println!("[WARN] ranking, without speedups, as the reference \
+                failed:"),
+        }
+        println!();
+        let width = ranked.iter().map(|&(n, _)| names[n].len()).max().unwrap();
+        for (rank, (n, measurement)) in ranked.into_iter().enumerate() {
+            let Measurement { min_time, summary, usage, counts, spread, .. } = measurement;
+            let speedup = reference_time
+                .map(|time| time.as_secs_f64() / min_time.as_secs_f64());
+            println!("{}{:>2}. {:width$}  {:>12}  {:>12}  {}",
+                INFO_INDENT, rank + 1, names[n],
+                Millis::from(min_time).to_string(),
+                format!("median {}", Millis::from(summary.median)),
+                match speedup {
+                    Some(speedup) => format!("{:.2}x", speedup),
+                    None => String::new(),
+                },
+                width = width);
+            table.write(CompareRow {
+                rank: rank + 1,
+                major,
+                minor: minors[n],
+                demo: names[n].clone(),
+                best_time_ms: Millis::from(min_time),
+                speedup,
+                summary,
+                spread,
+                usage,
+                counts,
+            })?;
+        }
+        println!();
+    }
+
+    if failed.is_empty() {
+        println!("[INFO] done");
+        Ok(())
+    } else {
+        Err(Error::BatchFailed {
+            failed,
+            total: minors.len(),
+        })
+    }
+}
@@ -1,5 +1,12 @@
 
-use crate::navigate::{find_demo, DemoLookup};
+use crate::{
+    cap_parse,
+    navigate::{find_demo, query_versions, DemoLookup, VersionQuery},
+    config::Config,
+    cache::{build_dir, object_dir, staging_dir, StableHasher},
+    quant::subproc,
+    error::{Error, Result},
+};
 use std::{
     path::{Path, PathBuf},
     ffi::{OsStr, OsString},
@@ -8,16 +15,28 @@ use std::{
         read_to_string,
         read_dir,
         create_dir_all,
+        remove_dir_all,
         FileType
     },
     env,
-    collections::HashMap,
+    collections::{HashMap, HashSet, BTreeMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    mem::take,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    hash::{Hash, Hasher},
+    time::UNIX_EPOCH,
     process::{
         Command,
         ExitStatus,
     }
 };
 use rand::prelude::*;
+use regex::Regex;
 
 /// Result of code compilation.
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -27,67 +46,107 @@ pub struct Compiled {
 }
 
 /// List names of C++ source code files directly in a directory.
-pub fn cpp_files<P: AsRef<Path>>(path: P) -> impl Iterator<Item=OsString> {
-    read_dir(path).unwrap()
-        .filter_map(Result::ok)
+pub fn cpp_files<P: AsRef<Path>>(path: P) -> Result<Vec<OsString>> {
+    let path = path.as_ref();
+    Ok(read_dir(path).map_err(Error::io(path))?
+        .filter_map(|entry| entry.ok())
         .filter_map(|f| f.file_type().ok()
             .filter(FileType::is_file)
             .map(|_| f.path()))
         .filter_map(|p| p.extension()
             .filter(|e| *e == "cpp" || *e == "h")
             .and_then(|_| p.file_name().map(OsStr::to_owned)))
+        .collect())
 }
 
-/// Possible C++ compiler toolchain to invoke.
+/// Built-in C++ compiler toolchain.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
-#[allow(dead_code)]
 pub enum Compiler {
     ClangPp,
     // https://stackoverflow.com/questions/3178342/compiling-a-c-program-with-gcc#3206195
-    Gcc9,
+    // versioned gcc, as installed by homebrew
+    GccN(u32),
     // this seems to be the default installed in my arch linux
     Gcc,
     // try to get MKL working
     GccMkl,
 }
 
-/// Try to compile code in a subdirectory.
 impl Compiler {
-    fn compile<P>(self, path: P) -> ExitStatus 
-    where
-        P: AsRef<Path>,
-    {
+    /// Name of the compiler executable.
+    pub fn program(self) -> String {
+        match self {
+            Compiler::ClangPp => "clang++".to_owned(),
+            Compiler::GccN(n) => format!("gcc-{}", n),
+            Compiler::Gcc | Compiler::GccMkl => "gcc".to_owned(),
+        }
+    }
+
+    /// Whether this compiler seems usable on this machine.
+    pub fn is_available(self) -> bool {
+        self.profile().is_available()
+            && (self != Compiler::GccMkl || env::var_os("MKLROOT").is_some())
+    }
+
+    /// Detect which compilers are installed, in order of preference.
+    pub fn detect() -> Vec<Compiler> {
+        let mut found = Vec::new();
+        if Compiler::GccMkl.is_available() {
+            found.push(Compiler::GccMkl);
+        }
+        if Compiler::Gcc.is_available() {
+            found.push(Compiler::Gcc);
+        }
+        let pat = Regex::new(r#"^gcc-(?P<n>\d+)$"#).unwrap();
+        let mut versions: Vec<u32> = env::var_os("PATH")
+            .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|dir| read_dir(dir).ok())
+            .flat_map(|entries| entries.filter_map(|entry| entry.ok()))
+            .filter_map(|entry| entry.file_name().to_str()
+                .and_then(|name| pat.captures(name))
+                .and_then(|cap| cap_parse::<u32>(&cap, "n")))
+            .collect();
+        versions.sort_unstable_by(|a, b| b.cmp(a));
+        versions.dedup();
+        found.extend(versions.into_iter().map(Compiler::GccN));
+        if Compiler::ClangPp.is_available() {
+            found.push(Compiler::ClangPp);
+        }
+        found
+    }
+
+    /// The toolchain profile for this compiler.
+    pub fn profile(self) -> Profile {
+        fn words(s: &str) -> Vec<String> {
+            s.split_whitespace().map(String::from).collect()
+        }
+        
+        let name = self.to_string();
+        let program = self.program();
         match self {
-            Compiler::ClangPp => Command::new("clang++")
-                .args("-std=c++11 -stdlib=libc++ -w -O3".split_whitespace())
-                .args(cpp_files(&path))
-                .current_dir(&path)
-                .status().unwrap(),
-            Compiler::Gcc9 => Command::new("gcc-9")
-                .args("-x c++ -fopenmp -w -O3 ".split_whitespace())
-                .args(cpp_files(&path))
-                .arg("-lstdc++")
-                .current_dir(&path)
-                .status().unwrap(),
-            Compiler::Gcc => Command::new("gcc")
-                .args("-x c++ -fopenmp -w -O3 ".split_whitespace())
-                .args(cpp_files(&path))
-                .arg("-lstdc++")
-                .current_dir(&path)
-                .status().unwrap(),
-            Compiler::GccMkl => {
-                let mklroot = env::var("MKLROOT")
-                    .expect("missing required env var MKLROOT");
-                Command::new("gcc")
-                    .args(format!(
-                        "-x c++ -fopenmp -w -O3 -m64 -I{}/include",
-                        mklroot).split_whitespace())
-                    .args(cpp_files(&path))
-                    .args(format!(
-                        " -lstdc++  -L${}/lib/intel64 -Wl,--no-as-needed -lmkl_intel_ilp64 -lmkl_gnu_thread -lmkl_core -lgomp -lpthread -lm -ldl",
-                        mklroot).split_whitespace())
-                    .current_dir(&path)
-                    .status().unwrap()
+            Compiler::ClangPp => Profile {
+                name,
+                program,
+                compile_flags: words("-std=c++11 -stdlib=libc++ -w -O3"),
+                ..Profile::default()
+            },
+            Compiler::GccN(_) | Compiler::Gcc => Profile {
+                name,
+                program,
+                compile_flags: words("-x c++ -fopenmp -w -O3"),
+                link_flags: words("-lstdc++"),
+                ..Profile::default()
+            },
+            Compiler::GccMkl => Profile {
+                name,
+                program,
+                compile_flags: words("-x c++ -fopenmp -w -O3 -m64"),
+                include_dirs: vec!["${MKLROOT}/include".to_owned()],
+                lib_dirs: vec!["${MKLROOT}/lib/intel64".to_owned()],
+                link_flags: words("-lstdc++ -Wl,--no-as-needed -lmkl_intel_ilp64 -lmkl_gnu_thread -lmkl_core -lgomp -lpthread -lm -ldl"),
+                ..Profile::default()
             },
         }
 
@@ -97,82 +156,782 @@ impl Compiler {
     }
 }
 
-/// Compile code, get path to binary.
-pub fn compile(lookup: &DemoLookup, major: u32, minor: u32) -> Result<Compiled, ()> {
-    let path = find_demo(lookup, major, minor)?;
+impl Display for Compiler {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Compiler::GccMkl => f.write_str("gcc-mkl"),
+            compiler => f.write_str(&compiler.program()),
+        }
+    }
+}
+
+impl FromStr for Compiler {
+    type Err = ();
+    
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        match s {
+            "clang++" | "clang" => Ok(Compiler::ClangPp),
+            "gcc" => Ok(Compiler::Gcc),
+            "gcc-mkl" | "mkl" => Ok(Compiler::GccMkl),
+            s => s.strip_prefix("gcc-")
+                .and_then(|n| n.parse().ok())
+                .map(Compiler::GccN)
+                .ok_or(()),
+        }
+    }
+}
+
+/// Named C++ toolchain configuration.
+///
+/// Strings may reference env vars as `${NAME}`, which are expanded when
+/// compiling.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Profile {
+    pub name: String,
+    /// Compiler executable, either a path or a name on the `PATH`.
+    pub program: String,
+    /// Flags for compiling each source file, also passed when linking.
+    pub compile_flags: Vec<String>,
+    /// Flags for linking, after the object files.
+    pub link_flags: Vec<String>,
+    /// Passed as `-I` flags.
+    pub include_dirs: Vec<String>,
+    /// Passed as `-L` flags.
+    pub lib_dirs: Vec<String>,
+    /// Env vars for the compiler process.
+    pub env: BTreeMap<String, String>,
+    /// Preprocessor definitions, passed as `-DNAME=VALUE` flags.
+    pub defines: BTreeMap<String, String>,
+}
+
+impl Profile {
+    /// Whether the compiler executable can be found.
+    pub fn is_available(&self) -> bool {
+        self.resolve_program().is_some()
+    }
+
+    /// Path to the compiler executable, if it can be found.
+    pub fn resolve_program(&self) -> Option<PathBuf> {
+        let program = Path::new(&self.program);
+        if program.components().count() > 1 {
+            Some(program.to_owned()).filter(|path| path.is_file())
+        } else {
+            find_program(program)
+        }
+    }
+
+    /// Compiler arguments for compiling any source file, with env vars
+    /// expanded.
+    pub fn compile_args(&self) -> Result<Vec<OsString>> {
+        let mut args: Vec<OsString> = Vec::new();
+        for flag in &self.compile_flags {
+            args.push(self.expand(flag)?.into());
+        }
+        for dir in &self.include_dirs {
+            args.push(format!("-I{}", self.expand(dir)?).into());
+        }
+        for (name, value) in &self.defines {
+            args.push(format!("-D{}={}", name, self.expand(value)?).into());
+        }
+        Ok(args)
+    }
+
+    /// Compiler arguments following the object files when linking, with env
+    /// vars expanded.
+    pub fn link_args(&self) -> Result<Vec<OsString>> {
+        let mut args: Vec<OsString> = Vec::new();
+        for dir in &self.lib_dirs {
+            args.push(format!("-L{}", self.expand(dir)?).into());
+        }
+        for flag in &self.link_flags {
+            args.push(self.expand(flag)?.into());
+        }
+        Ok(args)
+    }
+
+    /// Expand `${NAME}` env var references, from the profile's env or the
+    /// process's.
+    pub fn expand(&self, s: &str) -> Result<String> {
+        let pat = Regex::new(r#"\$\{(?P<var>[[:word:]]+)\}"#).unwrap();
+        let mut missing = None;
+        let expanded = pat.replace_all(s, |cap: &regex::Captures| {
+            let var = &cap["var"];
+            self.env.get(var).cloned()
+                .or_else(|| env::var(var).ok())
+                .unwrap_or_else(|| {
+                    missing = Some(var.to_owned());
+                    String::new()
+                })
+        });
+        match missing {
+            None => Ok(expanded.into_owned()),
+            Some(var) => Err(Error::MissingEnv {
+                profile: self.name.clone(),
+                var,
+            }),
+        }
+    }
+
+    /// Compiler command with the profile's env vars set.
+    pub fn command(&self) -> Result<Command> {
+        let mut cmd = Command::new(self.expand(&self.program)?);
+        for (key, val) in &self.env {
+            cmd.env(key, self.expand(val)?);
+        }
+        Ok(cmd)
+    }
+
+    /// Try to compile one source file in a source directory to an object 
+    /// file, also writing a depfile listing the headers it includes.
+    fn compile_object<P1, P2, P3>(
+        &self,
+        src: P1,
+        file: &OsStr,
+        object: P2,
+        depfile: P3,
+        quiet: bool,
+    ) -> Result<(ExitStatus, Vec<String>)>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+        P3: AsRef<Path>,
+    {
+        let mut cmd = self.command()?;
+        cmd.args(self.compile_args()?)
+            .arg("-c")
+            .arg(file)
+            .arg("-o").arg(object.as_ref())
+            .arg("-MMD")
+            .arg("-MF").arg(depfile.as_ref())
+            // relative file names keep the depfile independent of where
+            // the sources were staged
+            .current_dir(&src);
+        subproc(cmd, quiet).map(|(status, lines, _)| (status, lines))
+    }
+
+    /// Try to link object files into an executable.
+    fn link<P: AsRef<Path>>(
+        &self,
+        objects: &[PathBuf],
+        binary: P,
+        quiet: bool,
+    ) -> Result<(ExitStatus, Vec<String>)> {
+        let binary = binary.as_ref();
+        let mut cmd = self.command()?;
+        cmd.args(&self.compile_flags.iter()
+                .map(|flag| self.expand(flag))
+                .collect::<Result<Vec<_>>>()?)
+            // undo any `-x c++`, so the objects aren't read as source
+            .args(["-x", "none"])
+            .args(objects)
+            .arg("-o").arg(binary)
+            .args(self.link_args()?);
+        if let Some(dir) = binary.parent() {
+            cmd.current_dir(dir);
+        }
+        subproc(cmd, quiet).map(|(status, lines, _)| (status, lines))
+    }
+}
+
+/// Where a compiler choice came from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CompilerSource {
+    Flag,
+    Env,
+    Config,
+    Detected,
+}
+
+/// Env var which may name the compiler to use.
+pub const COMPILER_ENV_VAR: &str = "CS39_COMPILER";
+
+/// Choose the compiler profile for this session.
+///
+/// In order of precedence: the `--compiler` flag, the `CS39_COMPILER` env
+/// var, the `compiler` key of the config file, and finally the most
+/// preferred compiler detected as installed. Named profiles may be defined
+/// in the config file or be built-in compilers.
+pub fn select_compiler(
+    flag: Option<&str>, 
+    config: &Config
+) -> Result<(Profile, CompilerSource)> {
+    let env = env::var(COMPILER_ENV_VAR).ok();
+    let named = flag.map(|name| (name, CompilerSource::Flag))
+        .or_else(|| env.as_deref().map(|name| (name, CompilerSource::Env)))
+        .or_else(|| config.compiler.as_deref()
+            .map(|name| (name, CompilerSource::Config)));
     
-    let compiler = Compiler::GccMkl;
+    if let Some((name, source)) = named {
+        let profile = config.profile(name)?;
+        if !profile.is_available() {
+            return Err(Error::CompilerUnavailable {
+                profile: profile.name,
+                program: profile.program,
+                available: config.available_profiles(),
+            });
+        }
+        Ok((profile, source))
+    } else {
+        Compiler::detect()
+            .into_iter()
+            .next()
+            .map(|compiler| (compiler.profile(), CompilerSource::Detected))
+            .ok_or(Error::NoCompiler)
+    }
+}
+
+/// Search the `PATH` for an executable.
+pub fn find_program<S: AsRef<OsStr>>(name: S) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name.as_ref()))
+        .find(|path| path.is_file())
+}
+
+/// Bump to invalidate every cached build when the build recipe changes.
+const BUILD_RECIPE_VERSION: u32 = 2;
+
+/// File marking a cached build as complete.
+pub const BUILD_STAMP: &str = "cs39-build";
+
+/// Read the C++ source files directly in a directory.
+pub fn read_sources<P: AsRef<Path>>(path: P) -> Result<HashMap<OsString, String>> {
+    let path = path.as_ref();
+    let mut code = HashMap::new();
+    for file in cpp_files(path)? {
+        let file_path = path.join(&file);
+        let content = read_to_string(&file_path)
+            .map_err(Error::io(file_path))?;
+        code.insert(file, content);
+    }
+    Ok(code)
+}
+
+/// Hash the identity of a profile's compiler executable, so that a compiler
+/// upgrade invalidates the cache.
+fn hash_program<H: Hasher>(profile: &Profile, hasher: &mut H) {
+    if let Some(program) = profile.resolve_program() {
+        program.hash(hasher);
+        if let Ok(meta) = program.metadata() {
+            meta.len().hash(hasher);
+            meta.modified().ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .hash(hasher);
+        }
+    }
+}
+
+/// Cache key for building some sources with a profile.
+///
+/// Covers the source contents, the profile including its defines and 
+/// expanded flags, and the identity of the compiler executable.
+pub fn build_key(
+    profile: &Profile, 
+    sources: &HashMap<OsString, String>
+) -> Result<u64> {
+    let mut hasher = StableHasher::new();
+    BUILD_RECIPE_VERSION.hash(&mut hasher);
+    profile.hash(&mut hasher);
+    profile.compile_args()?.hash(&mut hasher);
+    profile.link_args()?.hash(&mut hasher);
+    hash_program(profile, &mut hasher);
     
-    println!("[INFO] compiling with {:?}", compiler);
-    println!();
+    let mut files: Vec<(&OsString, &String)> = sources.iter().collect();
+    files.sort();
+    files.hash(&mut hasher);
     
-    let status = compiler.compile(&path);
+    Ok(hasher.finish())
+}
 
-    if !status.success() {
-        eprintln!();
-        eprintln!("[ERROR] compile failure {}", status.code().unwrap());
-        return Err(());
+/// Cache key for compiling object files with a profile.
+///
+/// Unlike `build_key`, ignores the sources and anything only used for
+/// linking.
+pub fn object_key(profile: &Profile) -> Result<u64> {
+    let mut hasher = StableHasher::new();
+    BUILD_RECIPE_VERSION.hash(&mut hasher);
+    profile.program.hash(&mut hasher);
+    profile.compile_args()?.hash(&mut hasher);
+    for (key, val) in &profile.env {
+        (key, profile.expand(val)?).hash(&mut hasher);
     }
+    hash_program(profile, &mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Parse the prerequisites of the first rule in a make-style depfile.
+pub fn parse_depfile(text: &str) -> Vec<String> {
+    let text = text.replace("\\\n", " ");
+    let rule = text.lines().next().unwrap_or("");
+    let prereqs = match rule.find(": ") {
+        Some(i) => &rule[i + 2..],
+        None => return Vec::new(),
+    };
     
-    Ok(Compiled {
-        workdir: path.clone(),
-        binary: path.join("a.out")
-    })
+    let mut deps = Vec::new();
+    let mut dep = String::new();
+    let mut chars = prereqs.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                dep.push(' ');
+                chars.next();
+            },
+            c if c.is_whitespace() => if !dep.is_empty() {
+                deps.push(take(&mut dep));
+            },
+            c => dep.push(c),
+        }
+    }
+    if !dep.is_empty() {
+        deps.push(dep);
+    }
+    deps
 }
 
-/// Read code to memory, modify, write to temp dir, compile, get path
-/// to binary.
-pub fn modify_compile<P, F>(
-    repo: P,
-    lookup: &DemoLookup, 
-    major: u32,
-    minor: u32, 
-    edit: F
-) -> Result<Compiled, ()> 
-where
-    P: AsRef<Path>,
-    F: FnOnce(&mut HashMap<OsString, String>),
-{
-    // find code
-    let path = find_demo(lookup, major, minor)?;
+/// Hash the current contents of an object file's dependencies.
+///
+/// Demo files are taken from the sources in memory, anything else is read
+/// relative to the source directory. `None` if a dependency is missing.
+fn deps_hash(
+    deps: &[String], 
+    src: &Path, 
+    sources: &HashMap<OsString, String>
+) -> Option<u64> {
+    let mut hasher = StableHasher::new();
+    for dep in deps {
+        dep.hash(&mut hasher);
+        match sources.get(OsStr::new(dep)) {
+            Some(content) => content.as_bytes().hash(&mut hasher),
+            None => fs::read(src.join(dep)).ok()?[..].hash(&mut hasher),
+        }
+    }
+    Some(hasher.finish())
+}
+
+/// Remove object files, and their depfiles, ignoring errors.
+fn remove_objects(objects: &[PathBuf]) {
+    for object in objects {
+        let _ = fs::remove_file(object);
+        let _ = fs::remove_file(object.with_extension("d"));
+    }
+}
+
+/// Find a cached object file for a source file, whose dependencies are all 
+/// unchanged.
+///
+/// Each cached object in `dir` is named by the hash of its dependencies at 
+/// the time it was compiled, next to its depfile.
+fn cached_object(
+    dir: &Path,
+    src: &Path,
+    sources: &HashMap<OsString, String>,
+) -> Option<PathBuf> {
+    read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension() == Some(OsStr::new("d")))
+        .find_map(|depfile| {
+            let stem = depfile.file_stem()?.to_str()?;
+            let deps = parse_depfile(&read_to_string(&depfile).ok()?);
+            let hash = deps_hash(&deps, src, sources)?;
+            let object = depfile.with_extension("o");
+            Some(object)
+                .filter(|object| stem == format!("{:016x}", hash) && object.is_file())
+        })
+}
+
+/// A demo build, ready to run.
+///
+/// Separating preparation from building lets many builds run concurrently 
+/// with `build_all`.
+#[derive(Debug, Clone)]
+pub struct BuildJob {
+    pub demo: (u32, u32),
+    /// Unmodified source directory of the demo.
+    pub demo_path: PathBuf,
+    pub profile: Profile,
+    /// Sources to build, by file name.
+    pub sources: HashMap<OsString, String>,
+    /// Whether the sources differ from `demo_path`, so must be written to a
+    /// staging directory.
+    pub modified: bool,
+}
+
+impl BuildJob {
+    /// Prepare to build a demo as is.
+    pub fn new(
+        lookup: &DemoLookup, 
+        profile: &Profile, 
+        major: u32, 
+        minor: u32
+    ) -> Result<Self> {
+        let demo_path = find_demo(lookup, major, minor)?;
+        let sources = read_sources(&demo_path)?;
+        Ok(BuildJob {
+            demo: (major, minor),
+            demo_path,
+            profile: profile.clone(),
+            sources,
+            modified: false,
+        })
+    }
     
-    // read code
-    let mut code: HashMap<OsString, String> = cpp_files(&path)
-        .map(|file| (
-            file.clone(), 
-            read_to_string(path.join(file)).unwrap()
-        ))
-        .collect();
+    /// Prepare to build a demo with modified code.
+    pub fn modified<F>(
+        lookup: &DemoLookup, 
+        profile: &Profile,
+        major: u32,
+        minor: u32, 
+        edit: F
+    ) -> Result<Self>
+    where
+        F: FnOnce(&mut HashMap<OsString, String>),
+    {
+        let mut job = BuildJob::new(lookup, profile, major, minor)?;
+        edit(&mut job.sources);
+        job.modified = true;
+        Ok(job)
+    }
+    
+    /// Cache key of this build.
+    pub fn key(&self) -> Result<u64> {
+        build_key(&self.profile, &self.sources)
+    }
+    
+    /// Build, or reuse a previous identical build.
+    ///
+    /// When `quiet`, compiler output is only shown if compilation fails. 
+    /// Modified code is written to a staging directory, which is removed 
+    /// after a successful build unless `keep_staging`.
+    pub fn build(&self, quiet: bool, keep_staging: bool) -> Result<Compiled> {
+        let key = self.key()?;
+        let build = build_dir(&self.demo_path, &self.profile, key)?;
+        let binary = build.join("a.out");
+        let stamp = build.join(BUILD_STAMP);
         
-    // code modification callback
-    edit(&mut code);
+        if stamp.is_file() && binary.is_file() {
+            println!("[INFO] reusing cached {} build {:?}", 
+                self.profile.name, build);
+            return Ok(Compiled {
+                workdir: build,
+                binary,
+            });
+        }
+        
+        if !self.modified {
+            return self.build_from(&self.demo_path, key, build, quiet);
+        }
+        
+        let staging = staging_dir()?;
+        println!("[INFO] writing modified code to {:?}", staging);
+        for (file, content) in &self.sources {
+            let path = staging.join(file);
+            fs::write(&path, content).map_err(Error::io(path))?;
+        }
+        
+        let result = self.build_from(&staging, key, build, quiet);
+        if result.is_ok() && !keep_staging {
+            if let Err(e) = remove_dir_all(&staging) {
+                println!("[WARN] failed to remove {:?}: {}", staging, e);
+            }
+        } else {
+            println!("[INFO] kept modified code in {:?}", staging);
+        }
+        result
+    }
     
-    // allocate temp directory
-    let temp = repo.as_ref().join("tmp").join(format!("rng-{}", random::<u16>()));
-    println!("[INFO] building code in {:?}", temp);
-    create_dir_all(&temp).unwrap();
+    /// Build from sources in a directory, into a build directory.
+    fn build_from(
+        &self, 
+        src: &Path, 
+        key: u64, 
+        build: PathBuf, 
+        quiet: bool
+    ) -> Result<Compiled> {
+        let binary = build.join("a.out");
+        let stamp = build.join(BUILD_STAMP);
+        
+        println!("[INFO] compiling demo {:?} with {}", self.demo, self.profile.name);
+        println!("[INFO] building code in {:?}", build);
+        if !quiet { println!(); }
+        
+        // compile each translation unit, unless an object file with the
+        // same dependencies is cached
+        let object_root = object_dir(
+            &self.demo_path, &self.profile, object_key(&self.profile)?)?;
+        let mut units: Vec<&OsString> = self.sources.keys()
+            .filter(|file| Path::new(file).extension() == Some(OsStr::new("cpp")))
+            .collect();
+        units.sort();
+        
+        let mut objects = Vec::new();
+        let mut uncached = Vec::new();
+        let mut compiled = 0;
+        for &unit in &units {
+            let dir = object_root.join(unit);
+            create_dir_all(&dir).map_err(Error::io(&dir))?;
+            let object = match cached_object(&dir, src, &self.sources) {
+                Some(object) => object,
+                None => {
+                    compiled += 1;
+                    let (object, cached) = self.compile_unit(src, unit, &dir, quiet)
+                        .inspect_err(|_| remove_objects(&uncached))?;
+                    if !cached {
+                        uncached.push(object.clone());
+                    }
+                    object
+                },
+            };
+            objects.push(object);
+        }
+        println!("[INFO] compiled {} of {} source files", compiled, units.len());
+        
+        // link under a unique name, so an identical build running
+        // concurrently never overwrites a binary while it runs, then move
+        // into place, before the stamp which marks the build as complete
+        let temp = build.join(format!("tmp-{:08x}.out", random::<u32>()));
+        let linked = self.profile.link(&objects, &temp, quiet);
+        // objects which couldn't be cached are only needed for this link
+        remove_objects(&uncached);
+        let (status, lines) = linked?;
+        if !status.success() {
+            let _ = fs::remove_file(&temp);
+            return Err(self.failed(status, &lines, quiet));
+        }
+        fs::rename(&temp, &binary).map_err(Error::io(&temp))?;
+        fs::write(&stamp, format!("{:016x}\n", key))
+            .map_err(Error::io(&stamp))?;
+        
+        Ok(Compiled {
+            workdir: build,
+            binary,
+        })
+    }
     
-    // save code
-    for (file, content) in code {
-        let path = temp.join(file);
-        fs::write(path, content).unwrap();
+    /// Compile one translation unit into an object cache directory, and
+    /// whether the object was cached for reuse, rather than left under a
+    /// temporary name.
+    fn compile_unit(
+        &self, 
+        src: &Path, 
+        unit: &OsStr, 
+        dir: &Path, 
+        quiet: bool
+    ) -> Result<(PathBuf, bool)> {
+        // compile under a unique name, in case an identical build is
+        // running concurrently, then move into place
+        let temp = dir.join(format!("tmp-{:08x}", random::<u32>()));
+        let object = temp.with_extension("o");
+        let depfile = temp.with_extension("d");
+        let (status, lines) = self.profile
+            .compile_object(src, unit, &object, &depfile, quiet)?;
+        if !status.success() {
+            let _ = fs::remove_file(&object);
+            let _ = fs::remove_file(&depfile);
+            return Err(self.failed(status, &lines, quiet));
+        }
+        
+        let deps = read_to_string(&depfile)
+            .map(|text| parse_depfile(&text))
+            .map_err(Error::io(&depfile))?;
+        let hash = match deps_hash(&deps, src, &self.sources) {
+            Some(hash) => hash,
+            // can't be reused, but can still be linked
+            None => return Ok((object, false)),
+        };
+        let name = dir.join(format!("{:016x}", hash));
+        let cached = name.with_extension("o");
+        // the depfile goes last, as it marks the object as complete
+        fs::rename(&object, &cached).map_err(Error::io(&object))?;
+        fs::rename(&depfile, name.with_extension("d"))
+            .map_err(Error::io(&depfile))?;
+        Ok((cached, true))
     }
     
-    let compiler = Compiler::Gcc9;
+    /// Report a failed compiler invocation.
+    fn failed(&self, status: ExitStatus, lines: &[String], quiet: bool) -> Error {
+        if quiet {
+            for line in lines {
+                eprintln!("{}", line);
+            }
+        }
+        Error::CompileFailed {
+            demo: self.demo,
+            profile: self.profile.name.clone(),
+            status,
+        }
+    }
+}
+
+/// How tasks build demos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BuildOptions {
+    /// Number of builds to run at once.
+    pub jobs: usize,
+    /// Keep the staging directories of modified code after successful
+    /// builds.
+    pub keep_staging: bool,
+    /// Override `#ifndef`-guarded defines with `-D` flags rather than by
+    /// rewriting the source.
+    pub inject_defines: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            jobs: num_cpus::get(),
+            keep_staging: false,
+            inject_defines: false,
+        }
+    }
+}
+
+/// Run many builds, up to `options.jobs` at a time.
+///
+/// Results are in the same order as the jobs. Compiler output is only shown
+/// for failed builds.
+pub fn build_all(jobs: &[BuildJob], options: &BuildOptions) -> Vec<Result<Compiled>> {
+    // identical builds would clobber each other's build directory, so only
+    // the first of each runs concurrently, and the rest hit the cache after
+    let mut seen = HashSet::new();
+    let (unique, duplicate): (Vec<usize>, Vec<usize>) = (0..jobs.len())
+        .partition(|&i| match jobs[i].key() {
+            Ok(key) => seen.insert(key),
+            Err(_) => true,
+        });
     
-    println!("[INFO] compiling with {:?}", compiler);
-    println!();
+    let mut results: Vec<Option<Result<Compiled>>> = 
+        (0..jobs.len()).map(|_| None).collect();
     
-    let status = compiler.compile(&temp);
-    if !status.success() {
-        eprintln!();
-        eprintln!("[ERROR] compile failure {}", status.code().unwrap());
-        return Err(());
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.max(1).min(unique.len()) {
+            scope.spawn(|| loop {
+                let n = next.fetch_add(1, Ordering::SeqCst);
+                let i = match unique.get(n) {
+                    Some(&i) => i,
+                    None => break,
+                };
+                let result = jobs[i].build(true, options.keep_staging);
+                done.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    for (i, result) in done.into_inner().unwrap() {
+        results[i] = Some(result);
+    }
+    for i in duplicate {
+        results[i] = Some(jobs[i].build(true, options.keep_staging));
     }
     
-    // done
-    Ok(Compiled {
-        workdir: temp.clone(),
-        binary: temp.join("a.out")
-    })
+    results.into_iter()
+        .map(|result| result.unwrap())
+        .collect()
+}
+
+/// Build every demo in a query ahead of benchmarking them.
+///
+/// Failures are left for the benchmark phase to report, when it tries to
+/// build again.
+pub fn prebuild(
+    lookup: &DemoLookup,
+    query: VersionQuery,
+    profiles: &[Profile],
+    options: &BuildOptions,
+) -> Result<()> {
+    let mut jobs = Vec::new();
+    for (major, minor) in query_versions(lookup, query)? {
+        for profile in profiles {
+            jobs.push(BuildJob::new(lookup, profile, major, minor)?);
+        }
+    }
+    if jobs.len() > 1 {
+        println!("[INFO] building {} demos, {} at a time", 
+            jobs.len(), options.jobs);
+        let results = build_all(&jobs, options);
+        let failed = results.iter().filter(|result| result.is_err()).count();
+        if failed > 0 {
+            println!("[WARN] {} of {} builds failed", failed, jobs.len());
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Compile code, get path to binary.
+///
+/// Builds in the demo's build directory for the profile, leaving the source
+/// tree untouched, and reuses the binary if nothing changed.
+pub fn compile(
+    lookup: &DemoLookup, 
+    profile: &Profile,
+    major: u32, 
+    minor: u32
+) -> Result<Compiled> {
+    BuildJob::new(lookup, profile, major, minor)?.build(false, false)
+}
+
+/// Read code to memory, modify, write to a staging dir, compile, get path
+/// to binary.
+///
+/// If the modified code was built before, the cached binary is reused 
+/// without writing anything.
+pub fn modify_compile<F>(
+    lookup: &DemoLookup, 
+    profile: &Profile,
+    major: u32,
+    minor: u32, 
+    edit: F
+) -> Result<Compiled>
+where
+    F: FnOnce(&mut HashMap<OsString, String>),
+{
+    BuildJob::modified(lookup, profile, major, minor, edit)?.build(false, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_depfile_gcc() {
+        // as written by `g++ -MMD -MP`, wrapped, with phony header rules
+        let text = "\
+main.o: main.cpp Laplacian.h Parameters.h Utilities.h PointwiseOps.h \\
+ Reductions.h my\\ header.h\n\
+\n\
+Laplacian.h:\n\
+\n\
+Parameters.h:\n";
+        assert_eq!(parse_depfile(text), [
+            "main.cpp",
+            "Laplacian.h",
+            "Parameters.h",
+            "Utilities.h",
+            "PointwiseOps.h",
+            "Reductions.h",
+            "my header.h",
+        ]);
+    }
+
+    #[test]
+    fn parse_depfile_continuation_after_target() {
+        let text = "/tmp/obj/Laplacian.o: \\\n Laplacian.cpp \\\n Laplacian.h\n";
+        assert_eq!(parse_depfile(text), ["Laplacian.cpp", "Laplacian.h"]);
+    }
+
+    #[test]
+    fn parse_depfile_escaped_spaces() {
+        let text = "main.o: dir\\ with\\ spaces/main.cpp a\\ b.h\n";
+        assert_eq!(parse_depfile(text), ["dir with spaces/main.cpp", "a b.h"]);
+    }
+
+    #[test]
+    fn parse_depfile_malformed() {
+        assert!(parse_depfile("").is_empty());
+        assert!(parse_depfile("main.o main.cpp\n").is_empty());
+        assert!(parse_depfile("main.o:\n").is_empty());
+    }
 }
@@ -0,0 +1,147 @@
+use crate::{
+    navigate::repo_dir,
+    compile::{Compiler, Profile},
+    error::{Error, Result},
+};
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    fs::read_to_string,
+    io::ErrorKind,
+    env,
+};
+use serde::Deserialize;
+
+/// Env var which may override the config file location.
+pub const CONFIG_ENV_VAR: &str = "CS39_CONFIG";
+
+/// Contents of the `cs39.toml` config file.
+///
+/// Every key is optional, and a missing file is the same as an empty one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Compiler profile to use, eg. `"gcc"`, `"gcc-9"`, `"clang++"`, 
+    /// `"gcc-mkl"`, or the name of a profile defined in `profiles`.
+    pub compiler: Option<String>,
+    /// User-defined compiler profiles, by name.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// A `[profiles.NAME]` table of the config file.
+///
+/// Fields that are absent are inherited from the `extends` profile, which may
+/// be built-in or user-defined.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub extends: Option<String>,
+    pub program: Option<String>,
+    pub compile_flags: Option<Vec<String>>,
+    pub link_flags: Option<Vec<String>>,
+    pub include_dirs: Option<Vec<String>>,
+    pub lib_dirs: Option<Vec<String>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub defines: Option<BTreeMap<String, String>>,
+}
+
+impl Config {
+    /// Resolve a compiler profile by name.
+    ///
+    /// User-defined profiles take precedence over built-in compilers.
+    pub fn profile(&self, name: &str) -> Result<Profile> {
+        self.resolve(name, &mut Vec::new())
+    }
+    
+    fn resolve<'a>(
+        &'a self, 
+        name: &'a str, 
+        visiting: &mut Vec<&'a str>
+    ) -> Result<Profile> {
+        let user = match self.profiles.get(name) {
+            Some(user) => user,
+            None => return name.parse::<Compiler>()
+                .map(Compiler::profile)
+                .map_err(|()| Error::UnknownCompiler {
+                    name: name.to_owned(),
+                    available: self.available_profiles(),
+                }),
+        };
+        if visiting.contains(&name) {
+            return Err(Error::Config {
+                path: config_path()?,
+                message: format!("profile {:?} extends itself", name),
+            });
+        }
+        visiting.push(name);
+        
+        let mut profile = match &user.extends {
+            Some(base) => self.resolve(base, visiting)?,
+            None => Profile::default(),
+        };
+        profile.name = name.to_owned();
+        if let Some(program) = &user.program {
+            profile.program = program.clone();
+        }
+        if let Some(flags) = &user.compile_flags {
+            profile.compile_flags = flags.clone();
+        }
+        if let Some(flags) = &user.link_flags {
+            profile.link_flags = flags.clone();
+        }
+        if let Some(dirs) = &user.include_dirs {
+            profile.include_dirs = dirs.clone();
+        }
+        if let Some(dirs) = &user.lib_dirs {
+            profile.lib_dirs = dirs.clone();
+        }
+        if let Some(env) = &user.env {
+            profile.env.extend(env.clone());
+        }
+        if let Some(defines) = &user.defines {
+            profile.defines.extend(defines.clone());
+        }
+        if profile.program.is_empty() {
+            return Err(Error::Config {
+                path: config_path()?,
+                message: format!("profile {:?} has no program", name),
+            });
+        }
+        Ok(profile)
+    }
+    
+    /// Names of user-defined profiles and detected built-in compilers.
+    pub fn available_profiles(&self) -> Vec<String> {
+        self.profiles.keys().cloned()
+            .chain(Compiler::detect().iter().map(Compiler::to_string))
+            .collect()
+    }
+}
+
+/// Location of the config file.
+///
+/// `CS39_CONFIG` if set, otherwise `cs39.toml` in the repo root.
+pub fn config_path() -> Result<PathBuf> {
+    match env::var_os(CONFIG_ENV_VAR) {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => Ok(repo_dir()?.join("cs39.toml")),
+    }
+}
+
+/// Load the config file, if it exists.
+pub fn load_config() -> Result<Config> {
+    let path = config_path()?;
+    let text = match read_to_string(&path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            return Ok(Config::default());
+        },
+        Err(e) => return Err(Error::io(path)(e)),
+    };
+    toml::from_str(&text)
+        .map_err(|e| Error::Config {
+            path,
+            message: e.to_string(),
+        })
+}
@@ -0,0 +1,121 @@
+use crate::{
+    cpu_stat,
+    navigate::DemoLookup,
+    compile::{
+        compile,
+        Profile,
+    },
+    output::TableWriter,
+    quant::{
+        benchmark,
+        repeat_runs,
+        run_suffix,
+        BenchOptions,
+        Measurement,
+        ResourceUsage,
+    },
+    perf::PerfCounts,
+    stats::{RunSpread, SampleRow, Summary},
+    units::Millis,
+    error::Result,
+};
+use std::ops::RangeInclusive;
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+
+/// Header row of a CPU test table, with hardware event columns if `perf`.
+pub fn header(perf: bool) -> Vec<String> {
+    ["Threads", "BestTimeMs"].iter()
+        .chain(Summary::COLUMNS)
+        .chain(RunSpread::COLUMNS)
+        .chain(ResourceUsage::COLUMNS)
+        .chain(if perf { PerfCounts::COLUMNS } else { &[] })
+        .map(|&column| column.to_owned())
+        .collect()
+}
+
+/// Row of a CPU test table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuTestRow {
+    pub threads: u32,
+    pub best_time_ms: Millis,
+    pub summary: Summary,
+    /// `Some` if combined from several runs.
+    pub spread: Option<RunSpread>,
+    pub usage: ResourceUsage,
+    pub counts: Option<PerfCounts>,
+}
+
+impl Serialize for CpuTestRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        seq.serialize_element(&self.threads)?;
+        seq.serialize_element(&self.best_time_ms)?;
+        self.summary.serialize_columns(&mut seq)?;
+        RunSpread::serialize_columns(self.spread.as_ref(), &mut seq)?;
+        self.usage.serialize_columns(&mut seq)?;
+        if let Some(counts) = &self.counts {
+            counts.serialize_columns(&mut seq)?;
+        }
+        seq.end()
+    }
+}
+
+/// `cpu_test` task.
+///
+/// `options.threads` is ignored, as each thread count in `threads` is tested,
+/// `options.repeat` times each. Every iteration time is written to
+/// `samples`.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    lookup: &DemoLookup, 
+    profile: &Profile,
+    major: u32, 
+    minor: u32, 
+    threads: RangeInclusive<u32>,
+    options: &BenchOptions,
+    mut table: TableWriter<CpuTestRow>,
+    mut samples: TableWriter<SampleRow>,
+) -> Result<()> {
+    let compiled = compile(lookup, profile, major, minor)?;
+    
+    cpu_stat();
+    
+    let threads: Vec<u32> = threads.collect();
+    repeat_runs(threads.len(), options, |i, run| {
+        let cpu = threads[i];
+        println!("[INFO] benchmarking with {} thread{}", cpu, run_suffix(run, options));
+        let measurement = benchmark(
+            &compiled, major, minor, &BenchOptions {
+                threads: Some(cpu),
+                ..*options
+            })?;
+        for row in measurement.sample_rows(&cpu.to_string(), run) {
+            samples.write(row)?;
+        }
+        if options.repeat > 1 {
+            println!();
+        }
+        Ok(measurement)
+    }, |i, measurement| {
+        let Measurement { min_time, summary, usage, counts, spread, .. } = measurement?;
+        if let Some(spread) = &spread {
+            println!("[INFO] with {} thread, {}", threads[i], spread);
+        }
+        println!("[INFO] best time = {}", Millis::from(min_time));
+        
+        table.write(CpuTestRow {
+            threads: threads[i],
+            best_time_ms: Millis::from(min_time),
+            summary,
+            spread,
+            usage,
+            counts,
+        })?;
+        println!();
+        Ok(())
+    })?;
+    
+    println!("[INFO] done");
+    
+    Ok(())
+}
@@ -0,0 +1,370 @@
+use crate::{
+    error::{Error, Result},
+    navigate::{
+        DemoLookup,
+        find_demo,
+    },
+    compile::{
+        read_sources,
+        BuildJob,
+        Profile,
+    },
+};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt::{self, Display, Formatter},
+    mem::take,
+};
+use regex::{Captures, Regex};
+
+/// Object-like `#define` directive found in a demo.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Define {
+    pub name: String,
+    /// Replacement text, if any.
+    pub value: Option<String>,
+    pub file: OsString,
+    /// 1-based line number.
+    pub line: usize,
+    /// Whether the directive is inside an `#ifndef` for the same name, so 
+    /// it can be overridden with a `-D` flag.
+    pub guarded: bool,
+}
+
+/// Match an object-like `#define NAME [value] [comment]` directive,
+/// capturing its `indent`, `name`, `value`, and trailing `comment`.
+fn define_captures(line: &str) -> Option<Captures<'_>> {
+    let pat = r#"^(?P<indent>\s*)#\s*define\s+(?P<name>[[:alpha:]_][[:word:]]*)(?:\s+(?P<value>.*?))?\s*(?P<comment>(?://|/\*).*)?$"#;
+    let pat = Regex::new(pat).unwrap();
+
+    pat.captures(line)
+}
+
+/// Parse an object-like `#define NAME [value]` directive into its name and
+/// value, without any trailing comment.
+pub fn parse_define_line(line: &str) -> Option<(String, Option<String>)> {
+    define_captures(line)
+        .map(|caps| (
+            caps["name"].to_owned(),
+            caps.name("value")
+                .map(|m| m.as_str().to_owned())
+                .filter(|value| !value.is_empty()),
+        ))
+}
+
+/// Parse an `#ifndef NAME` directive into its name.
+pub fn parse_ifndef_line(line: &str) -> Option<String> {
+    let pat = r#"^\s*#\s*ifndef\s+(?P<name>[[:alpha:]_][[:word:]]*)\s*$"#;
+    let pat = Regex::new(pat).unwrap();
+
+    pat.captures(line).map(|caps| caps["name"].to_owned())
+}
+
+/// Find all object-like `#define` directives in one file, in order.
+pub fn scan_defines(file: &OsStr, code: &str) -> Vec<Define> {
+    let mut defines = Vec::new();
+    let mut guard: Option<String> = None;
+    for (i, line) in code.lines().enumerate() {
+        if let Some((name, value)) = parse_define_line(line) {
+            defines.push(Define {
+                guarded: guard.as_ref() == Some(&name),
+                name,
+                value,
+                file: file.to_owned(),
+                line: i + 1,
+            });
+        }
+        if !line.trim().is_empty() {
+            guard = parse_ifndef_line(line);
+        }
+    }
+    defines
+}
+
+/// Find all object-like `#define` directives in a demo, ordered by file
+/// name and line.
+pub fn find_defines(
+    lookup: &DemoLookup,
+    major: u32,
+    minor: u32
+) -> Result<Vec<Define>> {
+    let path = find_demo(lookup, major, minor)?;
+    let mut sources: Vec<(OsString, String)> = read_sources(&path)?
+        .into_iter()
+        .collect();
+    sources.sort();
+
+    Ok(sources.iter()
+        .flat_map(|(file, code)| scan_defines(file, code))
+        .collect())
+}
+
+/// Value to give a `#define` directive.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DefineValue {
+    /// `#define NAME value`
+    Value(String),
+    /// `#define NAME`, written as `defined`.
+    Defined,
+    /// No directive, written as `undef`.
+    Undefined,
+}
+
+impl Display for DefineValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DefineValue::Value(value) => f.write_str(value),
+            DefineValue::Defined => f.write_str("defined"),
+            DefineValue::Undefined => f.write_str("undef"),
+        }
+    }
+}
+
+/// Construct the directive for a value, or `None` to remove it.
+pub fn format_define_line(name: &str, value: &DefineValue) -> Option<String> {
+    match value {
+        DefineValue::Value(value) => Some(format!("#define {} {}", name, value)),
+        DefineValue::Defined => Some(format!("#define {}", name)),
+        DefineValue::Undefined => None,
+    }
+}
+
+/// Rewrite the directives for some defines in a demo's code, keeping their
+/// indentation and trailing comments.
+pub fn rewrite_defines(
+    code: &mut HashMap<OsString, String>,
+    settings: &[(&str, &DefineValue)],
+) {
+    for (file, content) in take(code) {
+        let rewritten: String = content.lines()
+            .map(|line: &str| {
+                let mut line = line.to_owned();
+                if let Some(caps) = define_captures(&line) {
+                    if let Some(&(name, value)) = settings.iter()
+                        .find(|&&(setting, _)| setting == &caps["name"])
+                    {
+                        // keep the line, so line numbers don't change
+                        let mut rest = format_define_line(name, value)
+                            .unwrap_or_default();
+                        if let Some(comment) = caps.name("comment") {
+                            if !rest.is_empty() {
+                                rest.push(' ');
+                            }
+                            rest.push_str(comment.as_str());
+                        }
+                        if !rest.is_empty() {
+                            rest.insert_str(0, &caps["indent"]);
+                        }
+                        line = rest;
+                    }
+                }
+                line.push('\n');
+                line
+            })
+            .collect();
+        code.insert(file, rewritten);
+    }
+}
+
+/// Find the one directive for a name, erroring if there are none or many.
+pub fn find_define<'a>(
+    defines: &'a [Define],
+    demo: (u32, u32),
+    name: &str,
+) -> Result<&'a Define> {
+    let mut found = defines.iter().filter(|define| define.name == name);
+    let first = found.next()
+        .ok_or_else(|| Error::MissingDefine {
+            demo,
+            name: name.to_owned(),
+        })?;
+    if let Some(again) = found.next() {
+        return Err(Error::DuplicateDefine {
+            demo,
+            name: name.to_owned(),
+            file: again.file.clone(),
+            line: again.line,
+            first_file: first.file.clone(),
+            first_line: first.line,
+        });
+    }
+    Ok(first)
+}
+
+/// How a define is overridden.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Mechanism {
+    /// Passed as a `-DNAME=VALUE` compiler flag, which the source's 
+    /// `#ifndef` guard defers to.
+    Flag,
+    /// The directive is rewritten in a modified copy of the source.
+    Rewrite,
+}
+
+impl Display for Mechanism {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Mechanism::Flag => f.write_str("-D flag"),
+            Mechanism::Rewrite => f.write_str("source rewrite"),
+        }
+    }
+}
+
+/// Choose how to override a define with some values.
+///
+/// Flags are only used if `inject` is set and the directive is guarded, and
+/// can't remove a directive.
+pub fn choose_mechanism(
+    define: &Define, 
+    values: &[DefineValue], 
+    inject: bool
+) -> Mechanism {
+    if inject && define.guarded && !values.contains(&DefineValue::Undefined) {
+        Mechanism::Flag
+    } else {
+        Mechanism::Rewrite
+    }
+}
+
+/// Override of a define's value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Override {
+    pub name: String,
+    pub value: DefineValue,
+    pub mechanism: Mechanism,
+}
+
+/// Prepare to build a demo with some defines overridden.
+///
+/// The source is only modified if some override is by rewriting.
+pub fn override_job(
+    lookup: &DemoLookup,
+    profile: &Profile,
+    major: u32,
+    minor: u32,
+    overrides: &[Override],
+) -> Result<BuildJob> {
+    let mut profile = profile.clone();
+    let mut rewrites: Vec<(&str, &DefineValue)> = Vec::new();
+    for o in overrides {
+        match (o.mechanism, &o.value) {
+            (Mechanism::Flag, DefineValue::Value(value)) => {
+                profile.defines.insert(o.name.clone(), value.clone());
+            },
+            (Mechanism::Flag, DefineValue::Defined) => {
+                profile.defines.insert(o.name.clone(), String::new());
+            },
+            _ => rewrites.push((&o.name, &o.value)),
+        }
+    }
+    
+    if rewrites.is_empty() {
+        BuildJob::new(lookup, &profile, major, minor)
+    } else {
+        BuildJob::modified(lookup, &profile, major, minor, 
+            |code| rewrite_defines(code, &rewrites))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(code: &str) -> Vec<Define> {
+        scan_defines(OsStr::new("main.cpp"), code)
+    }
+
+    #[test]
+    fn parse_define_variants() {
+        let parse = |line| parse_define_line(line);
+        let value = |name: &str, value: &str| Some((name.to_owned(), Some(value.to_owned())));
+        assert_eq!(parse("#define XDIM 256"), value("XDIM", "256"));
+        assert_eq!(parse("#  define XDIM 256"), value("XDIM", "256"));
+        assert_eq!(parse("  #define\tXDIM   256  "), value("XDIM", "256"));
+        assert_eq!(parse("# define SCALE (XDIM * 2)"), value("SCALE", "(XDIM * 2)"));
+        assert_eq!(parse("#define XDIM 256 // grid width"), value("XDIM", "256"));
+        assert_eq!(parse("#define XDIM 256 /* grid width */"), value("XDIM", "256"));
+        assert_eq!(parse("#define DO_NOT_USE_MKL"), Some(("DO_NOT_USE_MKL".to_owned(), None)));
+        assert_eq!(parse("#define DO_NOT_USE_MKL // on"), Some(("DO_NOT_USE_MKL".to_owned(), None)));
+        assert_eq!(parse("#define MAX(a, b) a"), None);
+        assert_eq!(parse("// #define XDIM 256"), None);
+        assert_eq!(parse("#defineXDIM 256"), None);
+        assert_eq!(parse("#undef XDIM"), None);
+    }
+
+    #[test]
+    fn guarded_and_unguarded() {
+        let defines = scan("\
+#ifndef XDIM
+#define XDIM 256
+#endif
+#define YDIM 128
+
+#ifndef ZDIM
+
+#  define ZDIM 64
+#endif
+#ifndef BLOCK_SIZE
+#define OTHER 1
+#define BLOCK_SIZE 8
+#endif
+");
+        let guarded: Vec<(&str, bool, usize)> = defines.iter()
+            .map(|define| (define.name.as_str(), define.guarded, define.line))
+            .collect();
+        assert_eq!(guarded, [
+            ("XDIM", true, 2),
+            ("YDIM", false, 4),
+            ("ZDIM", true, 8),
+            ("OTHER", false, 11),
+            ("BLOCK_SIZE", false, 12),
+        ]);
+        assert!(defines.iter().all(|define| define.file == "main.cpp"));
+    }
+
+    #[test]
+    fn duplicate_define() {
+        let defines = scan("#define XDIM 256\n#define YDIM 128\n#define XDIM 512\n");
+        match find_define(&defines, (0, 2), "XDIM") {
+            Err(Error::DuplicateDefine { line, first_line, .. }) => {
+                assert_eq!((first_line, line), (1, 3));
+            },
+            other => panic!("expected duplicate, got {:?}", other),
+        }
+        assert_eq!(find_define(&defines, (0, 2), "YDIM").unwrap().value,
+            Some("128".to_owned()));
+        assert!(matches!(find_define(&defines, (0, 2), "ZDIM"),
+            Err(Error::MissingDefine { .. })));
+    }
+
+    #[test]
+    fn rewrite_keeps_rest_of_line() {
+        let mut code: HashMap<OsString, String> = HashMap::new();
+        code.insert("main.cpp".into(), "\
+#include \"Parameters.h\"
+    #  define XDIM 256 // grid width
+#define YDIM 128
+#define DO_NOT_USE_MKL /* off */
+#define BLOCK_SIZE 8
+#define XDIMENSION 3
+int main() { return XDIM; }
+".to_owned());
+        let value = DefineValue::Value("1024".to_owned());
+        rewrite_defines(&mut code, &[
+            ("XDIM", &value),
+            ("YDIM", &DefineValue::Defined),
+            ("DO_NOT_USE_MKL", &DefineValue::Undefined),
+            ("BLOCK_SIZE", &DefineValue::Undefined),
+        ]);
+        assert_eq!(code[OsStr::new("main.cpp")], "\
+#include \"Parameters.h\"
+    #define XDIM 1024 // grid width
+#define YDIM
+/* off */
+
+#define XDIMENSION 3
+int main() { return XDIM; }
+");
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{
+    navigate::{find_demo, DemoLookup},
+    compile::read_sources,
+    output::{csv_path, INFO_INDENT},
+    baseline::{
+        self,
+        print_comparison,
+        read_samples,
+        samples_path,
+        CompareOptions,
+        Comparison,
+        ConfigSamples,
+    },
+    error::Result,
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsString,
+    path::Path,
+};
+use similar::{ChangeTag, TextDiff};
+
+/// Lines of unchanged context around each change, unless specified
+/// otherwise.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// How a source file differs between two demos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileChange {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+}
+
+/// Difference in one source file between two demos.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub file: OsString,
+    pub change: FileChange,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Unified diff, empty if unchanged.
+    pub unified: String,
+}
+
+/// Diff the sources of two demos, by file name, in order of file name.
+///
+/// `old_dir` and `new_dir` label the two sides in diff headers, as `a/` and
+/// `b/` do in git's.
+pub fn diff_sources(
+    old: &HashMap<OsString, String>,
+    new: &HashMap<OsString, String>,
+    old_dir: &str,
+    new_dir: &str,
+    context: usize,
+) -> Vec<FileDiff> {
+    let files: BTreeSet<&OsString> = old.keys().chain(new.keys()).collect();
+    files.into_iter()
+        .map(|file| {
+            let name = file.to_string_lossy();
+            let (old_text, new_text) = (old.get(file), new.get(file));
+            let change = match (old_text, new_text) {
+                (None, _) => FileChange::Added,
+                (_, None) => FileChange::Removed,
+                (Some(a), Some(b)) if a == b => FileChange::Unchanged,
+                _ => FileChange::Modified,
+            };
+            let old_header = match old_text {
+                Some(_) => format!("{}/{}", old_dir, name),
+                None => "/dev/null".to_owned(),
+            };
+            let new_header = match new_text {
+                Some(_) => format!("{}/{}", new_dir, name),
+                None => "/dev/null".to_owned(),
+            };
+
+            let empty = String::new();
+            let old_text = old_text.unwrap_or(&empty);
+            let new_text = new_text.unwrap_or(&empty);
+            let diff = TextDiff::from_lines(old_text, new_text);
+            let count = |tag| diff.iter_all_changes()
+                .filter(|change| change.tag() == tag)
+                .count();
+            let unified = match change {
+                FileChange::Unchanged => String::new(),
+                _ => diff.unified_diff()
+                    .context_radius(context)
+                    .header(&old_header, &new_header)
+                    .to_string(),
+            };
+            FileDiff {
+                file: file.clone(),
+                change,
+                insertions: count(ChangeTag::Insert),
+                deletions: count(ChangeTag::Delete),
+                unified,
+            }
+        })
+        .collect()
+}
+
+/// `diff` task.
+///
+/// Prints a summary of which source files changed from minor version `a` to
+/// `b`, then a unified diff of each, with `context` lines of context. With
+/// `timing`, also compares the latest benchmark results of the two.
+pub fn run(
+    lookup: &DemoLookup,
+    major: u32,
+    a: u32,
+    b: u32,
+    context: usize,
+    timing: bool,
+) -> Result<()> {
+    let (old_path, new_path) = (find_demo(lookup, major, a)?, find_demo(lookup, major, b)?);
+    let dir_name = |path: &Path| path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (old_dir, new_dir) = (dir_name(&old_path), dir_name(&new_path));
+    let diffs = diff_sources(
+        &read_sources(&old_path)?, &read_sources(&new_path)?,
+        &old_dir, &new_dir, context);
+
+    let changed: Vec<&FileDiff> = diffs.iter()
+        .filter(|diff| diff.change != FileChange::Unchanged)
+        .collect();
+    println!("[INFO] {} → {}: {} of {} files changed, {} insertions(+), {} deletions(-)",
+        old_dir, new_dir, changed.len(), diffs.len(),
+        changed.iter().map(|diff| diff.insertions).sum::<usize>(),
+        changed.iter().map(|diff| diff.deletions).sum::<usize>());
+    println!();
+    let width = changed.iter()
+        .map(|diff| diff.file.to_string_lossy().len())
+        .max()
+        .unwrap_or(0);
+    for diff in &changed {
+        let change = match diff.change {
+            FileChange::Added => "added",
+            FileChange::Removed => "removed",
+            _ => "modified",
+        };
+        println!("{}{:8}  {:width$}  +{} -{}", INFO_INDENT, change,
+            diff.file.to_string_lossy(), diff.insertions, diff.deletions,
+            width = width);
+    }
+    if !changed.is_empty() {
+        println!();
+    }
+    for diff in &changed {
+        print!("{}", diff.unified);
+    }
+    if !changed.is_empty() {
+        println!();
+    }
+
+    if timing {
+        timing_delta(major, a, b)?;
+    }
+    Ok(())
+}
+
+/// Compare the latest benchmark results of two minor versions, from
+/// `cpu_test` and `size_test` tables of each, and `compare` tables of both.
+fn timing_delta(major: u32, a: u32, b: u32) -> Result<()> {
+    let options = CompareOptions::default();
+    let mut found = false;
+
+    for &task in baseline::TASKS {
+        let old = read_samples(&samples_path(task, major, a)?)?;
+        let new = read_samples(&samples_path(task, major, b)?)?;
+        if let (Some(old), Some(new)) = (old, new) {
+            let pairs: Vec<(String, &ConfigSamples, &ConfigSamples)> = new.iter()
+                .filter_map(|new| old.iter()
+                    .find(|old| old.config == new.config)
+                    .map(|old| (new.config.clone(), old, new)))
+                .collect();
+            found |= print_timing(&format!("{} results", task), &pairs, &options);
+        }
+    }
+
+    let compared = read_samples(&csv_path(format!("compare_{}_samples.csv", major))?)?;
+    if let Some(compared) = compared {
+        let find = |minor: u32| compared.iter()
+            .find(|config| config.config == minor.to_string());
+        if let (Some(old), Some(new)) = (find(a), find(b)) {
+            found |= print_timing("compare results", &[(String::new(), old, new)],
+                &options);
+        }
+    }
+
+    if !found {
+        println!("[WARN] no benchmark results of both versions to compare; run \
+            cpu_test, size_test, or compare on both first");
+    }
+    Ok(())
+}
+
+/// Print comparisons of pairs of samples, labelled by configuration, if
+/// there are any.
+fn print_timing(
+    source: &str,
+    pairs: &[(String, &ConfigSamples, &ConfigSamples)],
+    options: &CompareOptions,
+) -> bool {
+    if pairs.is_empty() {
+        return false;
+    }
+    println!("[INFO] timing, from latest {}:", source);
+    println!();
+    let width = pairs.iter().map(|(config, _, _)| config.len()).max().unwrap_or(0);
+    for (config, old, new) in pairs {
+        let comparison = match Comparison::new(&old.samples_ms, &new.samples_ms, options) {
+            Some(comparison) => comparison,
+            None => continue,
+        };
+        print_comparison(config, &comparison, width);
+    }
+    println!();
+    true
+}
@@ -0,0 +1,311 @@
+use crate::{
+    cli::CliError,
+    output::{Indent, INFO_INDENT},
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+    ffi::OsString,
+    process::ExitStatus,
+    io,
+};
+
+/// Everything that can go wrong in a cs39 task.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid command line.
+    Cli(CliError),
+    /// No demo subdirectory has this major version.
+    MajorNotFound {
+        major: u32,
+        available: Vec<u32>,
+    },
+    /// The major version exists, but not this minor version.
+    MinorNotFound {
+        major: u32,
+        minor: u32,
+        subdir: PathBuf,
+        available: Vec<u32>,
+    },
+    /// A compiler needs an environment variable which isn't set.
+    MissingEnv {
+        profile: String,
+        var: String,
+    },
+    /// A compiler name was not recognized.
+    UnknownCompiler {
+        name: String,
+        available: Vec<String>,
+    },
+    /// A compiler was requested, but is not installed.
+    CompilerUnavailable {
+        profile: String,
+        program: String,
+        available: Vec<String>,
+    },
+    /// No compiler was requested, and none could be detected.
+    NoCompiler,
+    /// The config file is malformed.
+    Config {
+        path: PathBuf,
+        message: String,
+    },
+    /// A demo manifest is malformed.
+    Manifest {
+        path: PathBuf,
+        message: String,
+    },
+    /// The compiler ran, but failed.
+    CompileFailed {
+        demo: (u32, u32),
+        profile: String,
+        status: ExitStatus,
+    },
+    /// The demo binary ran, but failed.
+    DemoFailed {
+        demo: (u32, u32),
+        status: ExitStatus,
+    },
+    /// The demo binary did not print some expected output.
+    MissingOutput {
+        demo: (u32, u32),
+        expected: &'static str,
+    },
+    /// A preprocessor definition occurs more than once.
+    DuplicateDefine {
+        demo: (u32, u32),
+        name: String,
+        file: OsString,
+        line: usize,
+        first_file: OsString,
+        first_line: usize,
+    },
+    /// A required preprocessor definition is absent.
+    MissingDefine {
+        demo: (u32, u32),
+        name: String,
+    },
+    /// A demo has no `[A-Z]DIM` grid dimension definitions.
+    MissingDims {
+        demo: (u32, u32),
+    },
+    /// Some demos in a batch failed, and were already reported.
+    BatchFailed {
+        failed: Vec<(u32, u32)>,
+        total: usize,
+    },
+    /// Some compiler configurations of a demo failed, and were already
+    /// reported.
+    ConfigsFailed {
+        demo: (u32, u32),
+        failed: Vec<String>,
+        total: usize,
+    },
+    /// A demo has no benchmark results to save or compare.
+    NoResults {
+        demo: (u32, u32),
+        searched: Vec<PathBuf>,
+    },
+    /// A demo has no baseline of some name to compare against.
+    NoBaseline {
+        demo: (u32, u32),
+        name: String,
+        searched: Vec<PathBuf>,
+    },
+    /// A baseline or results table is malformed.
+    InvalidTable {
+        path: PathBuf,
+        message: String,
+    },
+    /// A demo got significantly slower than its baseline in some
+    /// configurations, which were already reported.
+    Regressions {
+        demo: (u32, u32),
+        regressed: Vec<String>,
+    },
+    /// `cargo install` failed.
+    ReinstallFailed {
+        status: ExitStatus,
+    },
+    /// Failure to launch a sub-process.
+    Spawn {
+        program: PathBuf,
+        source: io::Error,
+    },
+    /// Filesystem failure.
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Failure to write an output table.
+    Csv {
+        path: PathBuf,
+        source: csv::Error,
+    },
+}
+
+/// Result alias for cs39 tasks.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Construct a filesystem error for a path.
+    pub fn io<P: Into<PathBuf>>(path: P) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Io { path, source }
+    }
+
+    /// Construct a process spawn error for a program.
+    pub fn spawn<P: Into<PathBuf>>(program: P) -> impl FnOnce(io::Error) -> Self {
+        let program = program.into();
+        move |source| Error::Spawn { program, source }
+    }
+}
+
+impl From<CliError> for Error {
+    fn from(e: CliError) -> Self {
+        Error::Cli(e)
+    }
+}
+
+/// Human-readable form of an exit status.
+pub struct StatusDisplay(pub ExitStatus);
+
+impl Display for StatusDisplay {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0.code() {
+            Some(code) => write!(f, "exit code {}", code),
+            None => write!(f, "termination by signal"),
+        }
+    }
+}
+
+/// Comma-separated list of compilers.
+pub struct CompilerList<'a, T: Display>(pub &'a [T]);
+
+impl<'a, T: Display> Display for CompilerList<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none");
+        }
+        for (i, compiler) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", compiler)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Cli(e) => write!(f, "{}", e),
+            Error::MajorNotFound { major, available } => {
+                writeln!(f, "major version {} not found", major)?;
+                write!(f, "{}", Indent(INFO_INDENT,
+                    format_args!("available: {:?}", available)))
+            },
+            Error::MinorNotFound { major, minor, subdir, available } => {
+                writeln!(f, "minor version {} not found in {:?} (major {})",
+                    minor, subdir, major)?;
+                write!(f, "{}", Indent(INFO_INDENT,
+                    format_args!("available: {:?}", available)))
+            },
+            Error::MissingEnv { profile, var } =>
+                write!(f, "compiler {} requires env var {}", profile, var),
+            Error::UnknownCompiler { name, available } => {
+                writeln!(f, "unknown compiler {:?}", name)?;
+                write!(f, "{}", Indent(INFO_INDENT,
+                    format_args!("available: {}", CompilerList(available))))
+            },
+            Error::CompilerUnavailable { profile, program, available } => {
+                writeln!(f, "compiler {} is not available (program {:?})",
+                    profile, program)?;
+                write!(f, "{}", Indent(INFO_INDENT,
+                    format_args!("available: {}", CompilerList(available))))
+            },
+            Error::NoCompiler =>
+                write!(f, "no C++ compiler found on the PATH"),
+            Error::Config { path, message } => {
+                writeln!(f, "invalid config file {:?}", path)?;
+                write!(f, "{}", Indent(INFO_INDENT, message))
+            },
+            Error::Manifest { path, message } => {
+                writeln!(f, "invalid demo manifest {:?}", path)?;
+                write!(f, "{}", Indent(INFO_INDENT, message))
+            },
+            Error::CompileFailed { demo, profile, status } =>
+                write!(f, "compiling demo {:?} with {} failed with {}",
+                    demo, profile, StatusDisplay(*status)),
+            Error::DemoFailed { demo, status } =>
+                write!(f, "demo {:?} failed with {}",
+                    demo, StatusDisplay(*status)),
+            Error::MissingOutput { demo, expected } =>
+                write!(f, "demo {:?} did not report {}", demo, expected),
+            Error::DuplicateDefine {
+                demo, name, file, line, first_file, first_line,
+            } => {
+                writeln!(f, "{} defined twice in demo {:?}", name, demo)?;
+                writeln!(f, "{}", Indent(INFO_INDENT,
+                    format_args!("first at {:?}:{}", first_file, first_line)))?;
+                write!(f, "{}", Indent(INFO_INDENT,
+                    format_args!("again at {:?}:{}", file, line)))
+            },
+            Error::MissingDefine { demo, name } =>
+                write!(f, "{} not defined in demo {:?}", name, demo),
+            Error::MissingDims { demo } =>
+                write!(f, "no [A-Z]DIM dimensions defined in demo {:?}", demo),
+            Error::BatchFailed { failed, total } =>
+                write!(f, "{} of {} demos failed: {:?}",
+                    failed.len(), total, failed),
+            Error::ConfigsFailed { demo, failed, total } =>
+                write!(f, "{} of {} configurations of demo {:?} failed: {:?}",
+                    failed.len(), total, demo, failed),
+            Error::NoResults { demo, searched } => {
+                writeln!(f, "no benchmark results for demo {:?}", demo)?;
+                for path in searched {
+                    writeln!(f, "{}", Indent(INFO_INDENT,
+                        format_args!("not found: {:?}", path)))?;
+                }
+                write!(f, "{}", Indent(INFO_INDENT,
+                    "run cpu_test or size_test first"))
+            },
+            Error::NoBaseline { demo, name, searched } => {
+                writeln!(f, "no baseline {:?} for demo {:?}", name, demo)?;
+                for path in searched {
+                    writeln!(f, "{}", Indent(INFO_INDENT,
+                        format_args!("not found: {:?}", path)))?;
+                }
+                write!(f, "{}", Indent(INFO_INDENT,
+                    "save one with `cs39 baseline save`"))
+            },
+            Error::InvalidTable { path, message } => {
+                writeln!(f, "invalid table {:?}", path)?;
+                write!(f, "{}", Indent(INFO_INDENT, message))
+            },
+            Error::Regressions { demo, regressed } =>
+                write!(f, "demo {:?} regressed in {} configurations: {:?}",
+                    demo, regressed.len(), regressed),
+            Error::ReinstallFailed { status } =>
+                write!(f, "reinstalling failed with {}", StatusDisplay(*status)),
+            Error::Spawn { program, source } =>
+                write!(f, "failed to run {:?}: {}", program, source),
+            Error::Io { path, source } =>
+                write!(f, "i/o failure on {:?}: {}", path, source),
+            Error::Csv { path, source } =>
+                write!(f, "failed to write table {:?}: {}", path, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Spawn { source, .. } => Some(source),
+            Error::Io { source, .. } => Some(source),
+            Error::Csv { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
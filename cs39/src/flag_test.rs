@@ -0,0 +1,207 @@
+use crate::{
+    navigate::DemoLookup,
+    compile::{
+        build_all,
+        BuildJob,
+        BuildOptions,
+        Profile,
+    },
+    output::{
+        INFO_INDENT,
+        TableWriter,
+    },
+    quant::{
+        benchmark,
+        BenchOptions,
+        Measurement,
+    },
+    perf::PerfCounts,
+    stats::{SampleRow, Summary},
+    units::Millis,
+    error::{Error, Result},
+};
+use std::time::Duration;
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+
+/// Flag sets benchmarked when none are specified.
+pub const DEFAULT_FLAG_SETS: &[&str] = &[
+    "-O2",
+    "-O3",
+    "-Ofast",
+    "-O3 -march=native",
+    "-O3 -funroll-loops",
+];
+
+/// Parse `;`-separated flag sets, each being whitespace-separated flags.
+pub fn parse_flag_sets(s: &str) -> Vec<Vec<String>> {
+    s.split(';')
+        .map(|set| set.split_whitespace().map(String::from).collect())
+        .collect()
+}
+
+/// One compiler configuration to benchmark.
+#[derive(Debug, Clone)]
+pub struct FlagConfig {
+    pub profile: Profile,
+    /// Appended to the profile's compile flags, so they override it.
+    pub extra_flags: Vec<String>,
+}
+
+impl FlagConfig {
+    /// Every combination of profile and flag set.
+    pub fn matrix(profiles: &[Profile], flag_sets: &[Vec<String>]) -> Vec<Self> {
+        profiles.iter()
+            .flat_map(|profile| flag_sets.iter()
+                .map(move |flags| FlagConfig {
+                    profile: profile.clone(),
+                    extra_flags: flags.clone(),
+                }))
+            .collect()
+    }
+
+    /// The profile with the extra flags applied.
+    pub fn effective_profile(&self) -> Profile {
+        let mut profile = self.profile.clone();
+        profile.compile_flags.extend(self.extra_flags.iter().cloned());
+        profile
+    }
+
+    /// Short human-readable description.
+    pub fn label(&self) -> String {
+        if self.extra_flags.is_empty() {
+            self.profile.name.clone()
+        } else {
+            format!("{} {}", self.profile.name, self.extra_flags.join(" "))
+        }
+    }
+}
+
+/// Header row of a flag test table, with hardware event columns if `perf`.
+pub fn header(perf: bool) -> Vec<String> {
+    ["Profile", "Program", "ExtraFlags", "CompileFlags", "BestTimeMs"].iter()
+        .chain(Summary::COLUMNS)
+        .chain(if perf { PerfCounts::COLUMNS } else { &[] })
+        .map(|&column| column.to_owned())
+        .collect()
+}
+
+/// Row of a flag test table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlagTestRow {
+    pub profile: String,
+    pub program: String,
+    pub extra_flags: String,
+    pub compile_flags: String,
+    pub best_time_ms: Millis,
+    pub summary: Summary,
+    pub counts: Option<PerfCounts>,
+}
+
+impl Serialize for FlagTestRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        seq.serialize_element(&self.profile)?;
+        seq.serialize_element(&self.program)?;
+        seq.serialize_element(&self.extra_flags)?;
+        seq.serialize_element(&self.compile_flags)?;
+        seq.serialize_element(&self.best_time_ms)?;
+        self.summary.serialize_columns(&mut seq)?;
+        if let Some(counts) = &self.counts {
+            counts.serialize_columns(&mut seq)?;
+        }
+        seq.end()
+    }
+}
+
+/// `flag_test` task.
+///
+/// All configurations are built first, up to `options.jobs` at a time, then
+/// benchmarked one by one. Configurations which fail to build or run are
+/// reported and skipped, and the task fails at the end if any did. Every
+/// iteration time is written to `samples`, labelled by configuration.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    lookup: &DemoLookup,
+    configs: &[FlagConfig],
+    major: u32,
+    minor: u32,
+    options: &BuildOptions,
+    bench: &BenchOptions,
+    mut table: TableWriter<FlagTestRow>,
+    mut samples: TableWriter<SampleRow>,
+) -> Result<()> {
+    println!("[INFO] testing {} configurations:", configs.len());
+    for config in configs {
+        println!("{} • {}", INFO_INDENT, config.label());
+    }
+    println!();
+
+    let builds = configs.iter()
+        .map(|config| BuildJob::new(
+            lookup, &config.effective_profile(), major, minor))
+        .collect::<Result<Vec<_>>>()?;
+    let builds = build_all(&builds, options);
+    println!();
+
+    let mut results: Vec<(String, Duration)> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+
+    for (config, build) in configs.iter().zip(builds) {
+        println!("[INFO] benchmarking {}", config.label());
+        match build.and_then(|compiled| benchmark(&compiled, major, minor, bench)) {
+            Ok(measurement) => {
+                for row in measurement.sample_rows(&config.label(), 1) {
+                    samples.write(row)?;
+                }
+                let Measurement { min_time, summary, counts, .. } = measurement;
+                println!("[INFO] best time = {}", Millis::from(min_time));
+                let profile = config.effective_profile();
+                table.write(FlagTestRow {
+                    profile: config.profile.name.clone(),
+                    program: profile.program.clone(),
+                    extra_flags: config.extra_flags.join(" "),
+                    compile_flags: profile.compile_flags.join(" "),
+                    best_time_ms: Millis::from(min_time),
+                    summary,
+                    counts,
+                })?;
+                results.push((config.label(), min_time));
+            },
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                failed.push(config.label());
+            },
+        }
+        println!();
+    }
+
+    if !results.is_empty() {
+        let fastest = results.iter()
+            .map(|&(_, time)| time)
+            .min()
+            .unwrap();
+        results.sort_by_key(|&(_, time)| time);
+        let width = results.iter().map(|(label, _)| label.len()).max().unwrap();
+
+        println!("[INFO] ranking:");
+        println!();
+        for (label, time) in &results {
+            println!("{}{:width$}  {:>12}  {:.2}x",
+                INFO_INDENT, label, Millis::from(*time).to_string(),
+                time.as_secs_f64() / fastest.as_secs_f64(),
+                width = width);
+        }
+        println!();
+    }
+
+    if failed.is_empty() {
+        println!("[INFO] done");
+        Ok(())
+    } else {
+        Err(Error::ConfigsFailed {
+            demo: (major, minor),
+            failed,
+            total: configs.len(),
+        })
+    }
+}
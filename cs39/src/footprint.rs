@@ -0,0 +1,405 @@
+use crate::{
+    navigate::{DemoLookup, find_demo},
+    error::{Error, Result},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    fmt::{self, Display, Formatter},
+    fs::read_to_string,
+    io::ErrorKind,
+    iter::Peekable,
+    str::Chars,
+};
+use serde::Deserialize;
+
+/// File name of a demo manifest, in a demo directory or its parent.
+pub const MANIFEST_FILE: &str = "cs39-demo.toml";
+
+/// Contents of a `cs39-demo.toml` manifest.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Bytes of memory the demo works on, as an arithmetic expression over
+    /// its `#define`s, eg. `"5 * XDIM * YDIM * ZDIM * 4"`.
+    pub footprint: Option<String>,
+}
+
+/// Memory footprint model of a demo, declared by a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Footprint {
+    /// Manifest it was declared in.
+    pub path: PathBuf,
+    pub expr: Expr,
+}
+
+impl Footprint {
+    /// Evaluate the footprint in bytes, given the values of the demo's
+    /// numeric defines.
+    pub fn bytes(&self, vars: &BTreeMap<String, i128>) -> Result<u128> {
+        let bytes = self.expr.eval(vars)
+            .map_err(|message| Error::Manifest {
+                path: self.path.clone(),
+                message,
+            })?;
+        if bytes < 0 {
+            return Err(Error::Manifest {
+                path: self.path.clone(),
+                message: format!("footprint {} is negative", bytes),
+            });
+        }
+        Ok(bytes as u128)
+    }
+}
+
+impl Display for Footprint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} (from {:?})", self.expr, self.path)
+    }
+}
+
+/// Find the footprint model of a demo.
+///
+/// Taken from the demo directory's manifest, or if it has none, from its
+/// parent's, so that a family of demos can share one. `None` if neither
+/// declares a footprint.
+pub fn find_footprint(
+    lookup: &DemoLookup,
+    major: u32,
+    minor: u32,
+) -> Result<Option<Footprint>> {
+    let demo = find_demo(lookup, major, minor)?;
+    for dir in demo.ancestors().take(2) {
+        let path = dir.join(MANIFEST_FILE);
+        let manifest = match read_manifest(&path)? {
+            Some(manifest) => manifest,
+            None => continue,
+        };
+        if let Some(footprint) = manifest.footprint {
+            let expr = footprint.parse::<Expr>()
+                .map_err(|message| Error::Manifest {
+                    path: path.clone(),
+                    message: format!("footprint {:?}: {}", footprint, message),
+                })?;
+            return Ok(Some(Footprint { path, expr }));
+        }
+    }
+    Ok(None)
+}
+
+/// Read a manifest, or `None` if there isn't one.
+pub fn read_manifest(path: &Path) -> Result<Option<Manifest>> {
+    let text = match read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::io(path)(e)),
+    };
+    toml::from_str(&text)
+        .map(Some)
+        .map_err(|e| Error::Manifest {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })
+}
+
+/// Integer arithmetic expression over named variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Int(i128),
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+/// Binary operator of an `Expr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Op {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Op::Add),
+            '-' => Some(Op::Sub),
+            '*' => Some(Op::Mul),
+            '/' => Some(Op::Div),
+            _ => None,
+        }
+    }
+
+    fn binds_tighter(self) -> bool {
+        matches!(self, Op::Mul | Op::Div)
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+        })
+    }
+}
+
+impl Expr {
+    /// Evaluate, failing on unknown variables, overflow, or division by
+    /// zero. Division rounds towards zero, as in C.
+    pub fn eval(&self, vars: &BTreeMap<String, i128>) -> std::result::Result<i128, String> {
+        match self {
+            &Expr::Int(n) => Ok(n),
+            Expr::Var(name) => vars.get(name)
+                .copied()
+                .ok_or_else(|| format!("{} is not a numeric define", name)),
+            Expr::Neg(e) => e.eval(vars)?
+                .checked_neg()
+                .ok_or_else(|| format!("{} overflows", self)),
+            Expr::Binary(lhs, op, rhs) => {
+                let (a, b) = (lhs.eval(vars)?, rhs.eval(vars)?);
+                match op {
+                    Op::Add => a.checked_add(b),
+                    Op::Sub => a.checked_sub(b),
+                    Op::Mul => a.checked_mul(b),
+                    Op::Div if b == 0 => return Err(format!("{} divides by zero", self)),
+                    Op::Div => a.checked_div(b),
+                }.ok_or_else(|| format!("{} overflows", self))
+            },
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expr::Int(n) => write!(f, "{}", n),
+            Expr::Var(name) => f.write_str(name),
+            Expr::Neg(e) => match **e {
+                Expr::Binary(..) => write!(f, "-({})", e),
+                _ => write!(f, "-{}", e),
+            },
+            Expr::Binary(lhs, op, rhs) => {
+                // parenthesize only where precedence or associativity needs it
+                let wrap = |e: &Expr, right: bool| match e {
+                    &Expr::Binary(_, inner, _) =>
+                        (op.binds_tighter() && !inner.binds_tighter())
+                        || (right && op.binds_tighter() == inner.binds_tighter()),
+                    _ => false,
+                };
+                for (i, e) in [lhs, rhs].iter().enumerate() {
+                    if i == 1 {
+                        write!(f, " {} ", op)?;
+                    }
+                    if wrap(e, i == 1) {
+                        write!(f, "({})", e)?;
+                    } else {
+                        write!(f, "{}", e)?;
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = String;
+
+    /// Parse integers, identifiers, `+ - * /`, unary `-`, and parentheses,
+    /// with the usual precedence.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let mut parser = ExprParser { chars: s.chars().peekable() };
+        let expr = parser.sum()?;
+        match parser.next_nonblank() {
+            None => Ok(expr),
+            Some(c) => Err(format!("unexpected {:?}", c)),
+        }
+    }
+}
+
+/// Recursive descent parser for `Expr`.
+struct ExprParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek_nonblank(&mut self) -> Option<char> {
+        while let Some(c) = self.chars.peek() {
+            if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                return Some(*c);
+            }
+        }
+        None
+    }
+
+    fn next_nonblank(&mut self) -> Option<char> {
+        self.peek_nonblank()?;
+        self.chars.next()
+    }
+
+    /// Sequence of terms joined by `+` and `-`.
+    fn sum(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.peek_nonblank()
+            .and_then(Op::from_char)
+            .filter(|op| !op.binds_tighter())
+        {
+            self.chars.next();
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    /// Sequence of atoms joined by `*` and `/`.
+    fn product(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.atom()?;
+        while let Some(op) = self.peek_nonblank()
+            .and_then(Op::from_char)
+            .filter(|op| op.binds_tighter())
+        {
+            self.chars.next();
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.atom()?));
+        }
+        Ok(expr)
+    }
+
+    /// Integer, identifier, parenthesized expression, or negation of one.
+    fn atom(&mut self) -> std::result::Result<Expr, String> {
+        match self.peek_nonblank() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Expr::Neg(Box::new(self.atom()?)))
+            },
+            Some('(') => {
+                self.chars.next();
+                let expr = self.sum()?;
+                match self.next_nonblank() {
+                    Some(')') => Ok(expr),
+                    Some(c) => Err(format!("expected ')', found {:?}", c)),
+                    None => Err("unclosed '('".to_owned()),
+                }
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let digits = self.take_while(|c| c.is_ascii_digit());
+                digits.parse()
+                    .map(Expr::Int)
+                    .map_err(|_| format!("integer {} too large", digits))
+            },
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => Ok(Expr::Var(
+                self.take_while(|c| c.is_ascii_alphanumeric() || c == '_'))),
+            Some(c) => Err(format!("unexpected {:?}", c)),
+            None => Err("unexpected end".to_owned()),
+        }
+    }
+
+    fn take_while(&mut self, pred: fn(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek().filter(|&&c| pred(c)) {
+            s.push(c);
+            self.chars.next();
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> BTreeMap<String, i128> {
+        [("XDIM", 256), ("YDIM", 128), ("ZERO", 0), ("BIG", i128::MAX)].iter()
+            .map(|&(name, value)| (name.to_owned(), value))
+            .collect()
+    }
+
+    fn eval(s: &str) -> std::result::Result<i128, String> {
+        s.parse::<Expr>()?.eval(&vars())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4"), Ok(14));
+        assert_eq!(eval("2 * 3 + 4"), Ok(10));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("64 / 4 / 2"), Ok(8));
+        assert_eq!(eval("5 * XDIM * YDIM * 4"), Ok(5 * 256 * 128 * 4));
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(eval("(2 + 3) * 4"), Ok(20));
+        assert_eq!(eval("10 - (4 - 3)"), Ok(9));
+        assert_eq!(eval("((XDIM))"), Ok(256));
+        assert!(eval("(2 + 3").is_err());
+        assert!(eval("()").is_err());
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-3"), Ok(-3));
+        assert_eq!(eval("-XDIM + 1"), Ok(-255));
+        assert_eq!(eval("2 * -(3 + 4)"), Ok(-14));
+        assert_eq!(eval("5 - -2"), Ok(7));
+        assert_eq!(eval("- - 2"), Ok(2));
+    }
+
+    #[test]
+    fn division_rounds_towards_zero() {
+        assert_eq!(eval("7 / 2"), Ok(3));
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+    }
+
+    #[test]
+    fn unknown_variable() {
+        let err = eval("XDIM * WDIM").unwrap_err();
+        assert!(err.contains("WDIM"), "{}", err);
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(eval("BIG + 1").unwrap_err().contains("overflows"));
+        assert!(eval("BIG * 2").unwrap_err().contains("overflows"));
+        assert!(eval("-BIG - 2").unwrap_err().contains("overflows"));
+        assert!(eval("999999999999999999999999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(eval("XDIM / ZERO").unwrap_err().contains("divides by zero"));
+        assert!(eval("1 / (2 - 2)").unwrap_err().contains("divides by zero"));
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        assert!("XDIM * 4 )".parse::<Expr>().is_err());
+        assert!("XDIM 4".parse::<Expr>().is_err());
+        assert!("XDIM * 4 % 2".parse::<Expr>().is_err());
+        assert!("XDIM *".parse::<Expr>().is_err());
+        assert!("".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in [
+            "5 * XDIM * YDIM * 4",
+            "(XDIM + 1) * (YDIM - 2)",
+            "XDIM - (YDIM - 2)",
+            "XDIM / (YDIM * 2)",
+            "XDIM - YDIM + 2",
+            "-XDIM * -(YDIM + 1)",
+            "5 * XDIM * YDIM * 4 + (XDIM * YDIM + 1) * 4 + 7 * (XDIM - 2) * (YDIM - 2) * 8",
+        ] {
+            let expr: Expr = s.parse().unwrap();
+            assert_eq!(expr.to_string(), s);
+            assert_eq!(expr.to_string().parse::<Expr>(), Ok(expr));
+        }
+        let spaced: Expr = "((XDIM+1))*YDIM".parse().unwrap();
+        assert_eq!(spaced.to_string(), "(XDIM + 1) * YDIM");
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{read_dir, read_to_string},
+    path::Path,
+};
+
+/// Where Linux describes the CPUs.
+const CPU_SYSFS: &str = "/sys/devices/system/cpu";
+
+/// Where Linux describes the NUMA nodes.
+const NODE_SYSFS: &str = "/sys/devices/system/node";
+
+/// Kind of CPU cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// One level of CPU cache, as seen by the first CPU.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cache {
+    pub level: u32,
+    pub kind: CacheKind,
+    pub size_bytes: u64,
+    pub line_bytes: Option<u64>,
+    /// Number of logical CPUs sharing this cache.
+    pub shared_by: Option<usize>,
+}
+
+impl Cache {
+    /// Whether the cache holds data, rather than only instructions.
+    pub fn holds_data(&self) -> bool {
+        self.kind != CacheKind::Instruction
+    }
+}
+
+impl Display for Cache {
+    /// Short name, eg. `L1d`, `L2`.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "L{}{}", self.level, match self.kind {
+            CacheKind::Data => "d",
+            CacheKind::Instruction => "i",
+            CacheKind::Unified => "",
+        })
+    }
+}
+
+/// What could be detected about the CPU and memory system.
+///
+/// Only detected on Linux, from sysfs and `/proc/cpuinfo`. Anything which
+/// couldn't be detected is `None` or empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CpuInfo {
+    pub model: Option<String>,
+    /// Caches, from lowest to highest level.
+    pub caches: Vec<Cache>,
+    pub numa_nodes: Option<usize>,
+    /// cpufreq scaling governor of the first CPU, eg. `performance`.
+    pub governor: Option<String>,
+}
+
+impl CpuInfo {
+    /// Detect the CPU and memory system of this machine.
+    pub fn detect() -> Self {
+        let cpu0 = Path::new(CPU_SYSFS).join("cpu0");
+        let mut caches: Vec<Cache> = read_dir(cpu0.join("cache")).into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name()
+                .to_str()
+                .map(|name| name.starts_with("index"))
+                .unwrap_or(false))
+            .filter_map(|entry| read_cache(&entry.path()))
+            .collect();
+        caches.sort_by_key(|cache| (cache.level, cache.kind != CacheKind::Data));
+
+        let numa_nodes = read_dir(NODE_SYSFS).ok()
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name()
+                    .to_str()
+                    .and_then(|name| name.strip_prefix("node"))
+                    .map(|n| n.parse::<u32>().is_ok())
+                    .unwrap_or(false))
+                .count())
+            .filter(|&n| n > 0);
+
+        CpuInfo {
+            model: read_model(),
+            caches,
+            numa_nodes,
+            governor: read_trimmed(&cpu0.join("cpufreq").join("scaling_governor")),
+        }
+    }
+
+    /// The smallest data cache a working set of some size fits in, or `None`
+    /// if it only fits in main memory.
+    pub fn fits_in(&self, bytes: u128) -> Option<&Cache> {
+        self.caches.iter()
+            .filter(|cache| cache.holds_data())
+            .find(|cache| bytes <= cache.size_bytes as u128)
+    }
+
+    /// Short name of where a working set of some size fits, eg. `L2` or
+    /// `RAM`, or `None` if no caches were detected.
+    pub fn fits_in_name(&self, bytes: u128) -> Option<String> {
+        if self.caches.is_empty() {
+            return None;
+        }
+        Some(self.fits_in(bytes)
+            .map(Cache::to_string)
+            .unwrap_or_else(|| "RAM".to_owned()))
+    }
+}
+
+/// Describe a cache from its sysfs `cache/indexN` directory.
+fn read_cache(dir: &Path) -> Option<Cache> {
+    let level = read_trimmed(&dir.join("level"))?.parse().ok()?;
+    let kind = match read_trimmed(&dir.join("type"))?.as_str() {
+        "Data" => CacheKind::Data,
+        "Instruction" => CacheKind::Instruction,
+        "Unified" => CacheKind::Unified,
+        _ => return None,
+    };
+    Some(Cache {
+        level,
+        kind,
+        size_bytes: parse_size(&read_trimmed(&dir.join("size"))?)?,
+        line_bytes: read_trimmed(&dir.join("coherency_line_size"))
+            .and_then(|s| s.parse().ok()),
+        shared_by: read_trimmed(&dir.join("shared_cpu_list"))
+            .and_then(|s| count_cpu_list(&s)),
+    })
+}
+
+/// Parse a sysfs size, eg. `48K`.
+fn parse_size(s: &str) -> Option<u64> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let multiplier = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Count the CPUs in a sysfs CPU list, eg. `0-3,8-11`.
+fn count_cpu_list(s: &str) -> Option<usize> {
+    s.split(',')
+        .map(|range| match range.split_once('-') {
+            Some((start, end)) => {
+                let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+                end.checked_sub(start).map(|n| n + 1)
+            },
+            None => range.parse::<usize>().ok().map(|_| 1),
+        })
+        .sum()
+}
+
+/// CPU model name from `/proc/cpuinfo`.
+fn read_model() -> Option<String> {
+    read_to_string("/proc/cpuinfo").ok()?
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| matches!(key.trim(), "model name" | "Model" | "cpu model"))
+        .map(|(_, value)| value.trim().to_owned())
+        .filter(|model| !model.is_empty())
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("32K"), Some(32 << 10));
+        assert_eq!(parse_size("48K"), Some(48 << 10));
+        assert_eq!(parse_size("8192K"), Some(8 << 20));
+        assert_eq!(parse_size("1M"), Some(1 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("4096"), Some(4096));
+    }
+
+    #[test]
+    fn malformed_sizes() {
+        for s in ["", "K", "32k", "32KB", "32 K", "-32K", "1.5M", "99999999999999999999G"] {
+            assert_eq!(parse_size(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(count_cpu_list("0"), Some(1));
+        assert_eq!(count_cpu_list("0-3"), Some(4));
+        assert_eq!(count_cpu_list("0-3,8,10-11"), Some(7));
+        assert_eq!(count_cpu_list("0,2,4,6"), Some(4));
+        assert_eq!(count_cpu_list("0-63"), Some(64));
+    }
+
+    #[test]
+    fn malformed_cpu_lists() {
+        for s in ["", "0-", "-3", "3-1", "0-3,", "0,,2", "a-b", "0-3;8"] {
+            assert_eq!(count_cpu_list(s), None, "{:?}", s);
+        }
+    }
+}
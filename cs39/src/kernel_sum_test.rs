@@ -1,15 +1,20 @@
 use crate::{
     {cap_parse, cpu_stat},
+    error::{Error, Result},
     navigate::{
         DemoLookup,
     },
     compile::{
         compile,
         Compiled,
+        Profile,
     },
+    output::TableWriter,
     quant::{
         subproc,
+        ResourceUsage,
     },
+    units::{Millis, Secs},
 };
 use std::{
     process::Command,
@@ -18,14 +23,48 @@ use std::{
 };
 use regex::{self, Regex};
 use num_cpus;
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+
+/// Header row of a kernel sum test table.
+pub fn header() -> Vec<String> {
+    ["Threads", "TotalTimeMs", "KernelSumMs", "UnaccountedMs"].iter()
+        .chain(ResourceUsage::COLUMNS)
+        .map(|&column| column.to_owned())
+        .collect()
+}
+
+/// Row of a kernel sum test table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KernelSumRow {
+    pub threads: u32,
+    pub total_time_ms: Millis,
+    pub kernel_sum_ms: Millis,
+    /// `None` if the kernel times sum to more than the total.
+    pub unaccounted_ms: Option<Millis>,
+    pub usage: ResourceUsage,
+}
+
+impl Serialize for KernelSumRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(4 + ResourceUsage::COLUMNS.len()))?;
+        seq.serialize_element(&self.threads)?;
+        seq.serialize_element(&self.total_time_ms)?;
+        seq.serialize_element(&self.kernel_sum_ms)?;
+        seq.serialize_element(&self.unaccounted_ms)?;
+        self.usage.serialize_columns(&mut seq)?;
+        seq.end()
+    }
+}
 
 /// `kernel_sum_test` task.
 pub fn run(
     lookup: &DemoLookup, 
+    profile: &Profile,
     major: u32, 
     minor: u32,
     multithreaded: bool,
-) -> Result<(), ()> 
+    mut table: TableWriter<KernelSumRow>,
+) -> Result<()>
 {
     println!("[INFO] running kernel time sum test on demo {:?}", (major, minor));
     println!("[INFO] multithreading = {}", multithreaded);
@@ -35,18 +74,20 @@ pub fn run(
         false => 1,
     };
     
-    let Compiled { workdir, binary } = compile(lookup, major, minor)?;
+    let Compiled { workdir, binary } = compile(lookup, profile, major, minor)?;
     
     println!("[INFO] running");
-    let (status, lines) = subproc(
+    let (status, lines, usage) = subproc(
         Command::new(&binary)
             .current_dir(&workdir)
-            .env("OMP_NUM_THREADS", cpu.to_string()), true);
+            .env("OMP_NUM_THREADS", cpu.to_string()), true)?;
             
     if !status.success() {
-        println!("[ERROR] exit code {}", status.code().unwrap());
-        return Err(());
-    }   
+        return Err(Error::DemoFailed {
+            demo: (major, minor),
+            status,
+        });
+    }
     
     let mut sums: HashMap<String, Duration> = HashMap::new();
     let mut total: Option<Duration> = None;
@@ -55,9 +96,9 @@ pub fn run(
         if let Some((name, time)) = parse_kernel_run_line(&line) {
             *sums.entry(name).or_insert(Duration::from_secs(0)) += time;
         } else if let Some(time) = parse_entire_run_line(&line) {
-            if total.is_some() {
+            if let Some(old) = total {
                 println!("[WARN] \"Entire Run\" line occurred in duplicate");
-                println!("       Old time = {:?}", total.unwrap());
+                println!("       Old time = {:?}", old);
                 println!("       New time = {:?}", time);
             }
             
@@ -66,8 +107,9 @@ pub fn run(
     }
     
     let total = total
-        .ok_or_else(|| {
-            println!("[ERROR] program did not report \"Entire Run\" time");
+        .ok_or(Error::MissingOutput {
+            demo: (major, minor),
+            expected: "\"Entire Run\" time",
         })?;
     
     let grand_sum: Duration = sums.iter()
@@ -82,9 +124,13 @@ pub fn run(
     {
         let mut times: Vec<(&str, Duration)> = sums.iter().map(|(s, &d)| (s.as_str(), d)).collect();
         times.sort_by_key(|&(_, d)| -(d.as_millis() as i128));
-        let max_len = times.iter().map(|&(s, _)| s.len()).max().unwrap();
+        let max_len = times.iter().map(|&(s, _)| s.len()).max().unwrap_or(0);
         
-        let max_millis: u128 = times.iter().map(|&(_, d)| d.as_millis()).max().unwrap();
+        let max_millis: u128 = times.iter()
+            .map(|&(_, d)| d.as_millis())
+            .max()
+            .unwrap_or(0)
+            .max(1);
         let max_bars = 40;
         
         for &(s, d) in &times {
@@ -93,7 +139,7 @@ pub fn run(
             for _ in 0..(max_len - s.len() + 1) {
                 print!(" ");
             }
-            print!("{:.2}s", d.as_secs_f32());
+            print!("{}", Secs::from(d));
             
             print!(" ");
             let bars: u32 = ((d.as_millis() as f64 / max_millis as f64) * max_bars as f64) as u32;
@@ -105,38 +151,47 @@ pub fn run(
     }
     
     println!();
-    println!("[INFO] program-reported total time = {:.3}s", total.as_secs_f32());
-    println!("[INFO] sum of kernel times = {:.3}s", grand_sum.as_secs_f32());
+    println!("[INFO] program-reported total time = {:.3}", Secs::from(total));
+    println!("[INFO] sum of kernel times = {:.3}", Secs::from(grand_sum));
     if let Some(unaccounted) = unaccounted {
-        println!("[INFO] unaccounted time = {:.3}s", unaccounted.as_secs_f32());
+        println!("[INFO] unaccounted time = {:.3}", Secs::from(unaccounted));
     } else {
         println!("[INFO] unaccounted time = None");
     }
+    println!("[INFO] {}", usage);
     println!();
+    
+    table.write(KernelSumRow {
+        threads: cpu as _,
+        total_time_ms: Millis::from(total),
+        kernel_sum_ms: Millis::from(grand_sum),
+        unaccounted_ms: unaccounted.map(Millis::from),
+        usage,
+    })?;
     println!("[INFO] done");
     Ok(())
 }
 
-fn parse_kernel_run_line(line: &str) -> Option<(String, Duration)> {
+/// Parse a `[KERNEL name : Time = 1.234ms]` line.
+pub fn parse_kernel_run_line(line: &str) -> Option<(String, Duration)> {
     let pat = r##"^\[KERNEL (?P<name>.+) : Time = (?P<ms>\d+(?:\.\d+)?)ms\]$"##;
     let pat = Regex::new(pat).unwrap();
     pat
         .captures(line)
-        .map(|caps| {
-            let ms = cap_parse::<f64>(&caps, "ms").unwrap();
-            let time = Duration::from_secs_f64(ms / 1000.0);
-            
-            let name = cap_parse::<String>(&caps, "name").unwrap();
+        .and_then(|caps| {
+            let time = Millis(cap_parse::<f64>(&caps, "ms")?).to_duration()?;
+            let name = cap_parse::<String>(&caps, "name")?;
             
-            (name, time)
+            Some((name, time))
         })
 }
 
-fn parse_entire_run_line(line: &str) -> Option<Duration> {
+/// Parse an `[Entire Run : 1.234ms]` line.
+pub fn parse_entire_run_line(line: &str) -> Option<Duration> {
     let pat = r##"^\[Entire Run : (?P<ms>\d+(?:\.\d+)?)ms\]$"##;
     let pat = Regex::new(pat).unwrap();
     pat
         .captures(line)
-        .map(|caps| cap_parse::<f64>(&caps, "ms").unwrap())
-        .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        .and_then(|caps| cap_parse::<f64>(&caps, "ms"))
+        .and_then(|ms| Millis(ms).to_duration())
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+//! Library side of the cs39 CLI for the CS639S20 demos.
+//!
+//! Everything the CLI does is exposed here, so that custom experiments can
+//! be scripted in Rust on top of the same demo navigation, compilation,
+//! execution, output parsing, and reporting machinery.
+
+extern crate regex;
+extern crate num_cpus;
+extern crate rand;
+extern crate byte_unit;
+extern crate csv;
+extern crate serde;
+extern crate libc;
+extern crate similar;
+
+use crate::{
+    compile::{
+        compile,
+        Compiled,
+        Compiler,
+        Profile,
+    },
+    config::Config,
+    cache::{cache_dir, cache_usage},
+    hardware::CpuInfo,
+    navigate::DemoLookup,
+    output::{
+        Indent, 
+        INFO_INDENT,
+    },
+    error::{Error, Result, StatusDisplay, CompilerList},
+};
+use std::{
+    process::Command,
+    str::FromStr,
+};
+use byte_unit::Byte;
+
+/// C++ compilation.
+pub mod compile;
+
+/// Code demo navigation.
+pub mod navigate;
+
+/// Program output helpers.
+pub mod output;
+
+/// `size_test` task.
+pub mod size_test;
+
+/// Extraction of quantitative data from demos.
+pub mod quant;
+
+/// Statistical summaries of iteration times.
+pub mod stats;
+
+/// Typed time units for tables and console output.
+pub mod units;
+
+/// Hardware performance counters.
+pub mod perf;
+
+/// CPU, cache, and NUMA topology detection.
+pub mod hardware;
+
+/// Machine fingerprints for output files.
+pub mod metadata;
+
+/// `kernel_sum_test` task.
+pub mod kernel_sum_test;
+
+/// `cpu_test` task.
+pub mod cpu_test;
+
+/// `flag_test` task.
+pub mod flag_test;
+
+/// Preprocessor definitions in demo code.
+pub mod defines;
+
+/// `sweep` task.
+pub mod sweep;
+
+/// `compare` task.
+pub mod compare;
+
+/// `diff` task.
+pub mod diff;
+
+/// Demo manifests and memory footprint models.
+pub mod footprint;
+
+/// Benchmark baselines and regression detection.
+pub mod baseline;
+
+/// Command-line argument parsing.
+pub mod cli;
+
+/// Error handling.
+pub mod error;
+
+/// Config file.
+pub mod config;
+
+/// cs39-managed cache directory.
+pub mod cache;
+
+/// Extract and parse a regex capture group.
+pub fn cap_parse<T: FromStr>(cap: &regex::Captures, group: &str) -> Option<T> {
+    cap.name(group).and_then(|m| m.as_str().parse().ok())
+}
+
+/// `run` task.
+pub fn run_demo(
+    lookup: &DemoLookup, 
+    profile: &Profile,
+    major: u32, 
+    minor: u32
+) -> Result<()> {
+    let Compiled { workdir, binary } = compile(lookup, profile, major, minor)?;
+    
+    println!("[INFO] running");
+    println!();
+    let status = Command::new(&binary)
+        .current_dir(&workdir)
+        .status()
+        .map_err(Error::spawn(&binary))?;
+    println!();
+    if !status.success() {
+        return Err(Error::DemoFailed {
+            demo: (major, minor),
+            status,
+        });
+    }
+    println!("[INFO] {}", StatusDisplay(status));
+    
+    Ok(())
+}
+
+/// `stat` subtask, listing detected compilers and configured profiles.
+pub fn compiler_stat(config: &Config) {
+    let profiles: Vec<&String> = config.profiles.keys().collect();
+    println!("[INFO] compiler info:");
+    println!("{}", Indent(INFO_INDENT, ""));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("DETECTED = {}", CompilerList(&Compiler::detect()))));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("PROFILES = {}", CompilerList(&profiles))));
+    println!("{}", Indent(INFO_INDENT, ""));
+}
+
+/// `stat` subtask, reporting the disk usage of the cache.
+pub fn cache_stat() -> Result<()> {
+    let usage = cache_usage()?;
+    println!("[INFO] cache info:");
+    println!("{}", Indent(INFO_INDENT, ""));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("PATH = {:?}", cache_dir()?)));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("BUILDS = {}", usage.builds)));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("OBJECT FILES = {}", usage.objects)));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("STAGING DIRS = {}", usage.staging)));
+    println!("{}", Indent(INFO_INDENT, ""));
+    Ok(())
+}
+
+/// `stat` task/subtask.
+pub fn cpu_stat() {
+    let info = CpuInfo::detect();
+    let unknown = || "unknown".to_owned();
+    println!("[INFO] cpu info:");
+    println!("{}", Indent(INFO_INDENT, ""));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("MODEL = {}", info.model.clone().unwrap_or_else(unknown))));
+    println!("{}", Indent(INFO_INDENT, 
+        format_args!("LOGICAL CPUS = {}", num_cpus::get())));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("PHYSICAL CPUS = {}", num_cpus::get_physical())));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("NUMA NODES = {}", info.numa_nodes
+            .map(|n| n.to_string())
+            .unwrap_or_else(unknown))));
+    println!("{}", Indent(INFO_INDENT,
+        format_args!("GOVERNOR = {}", info.governor.clone().unwrap_or_else(unknown))));
+    if info.caches.is_empty() {
+        println!("{}", Indent(INFO_INDENT, "CACHES = unknown"));
+    }
+    for cache in &info.caches {
+        let mut details = Byte::from_bytes(cache.size_bytes as u128)
+            .get_appropriate_unit(true)
+            .format(0);
+        if let Some(line) = cache.line_bytes {
+            details += &format!(", {} B lines", line);
+        }
+        if let Some(cpus) = cache.shared_by {
+            details += &format!(", shared by {} logical CPUs", cpus);
+        }
+        println!("{}", Indent(INFO_INDENT,
+            format_args!("{} CACHE = {}", cache, details)));
+    }
+    println!("{}", Indent(INFO_INDENT, ""));
+}
@@ -1,248 +1,450 @@
-extern crate regex;
-extern crate num_cpus;
-extern crate rand;
-extern crate byte_unit;
-extern crate csv;
-extern crate serde;
+extern crate cs39;
 
-use crate::{
-    compile::{
-        compile,
-        Compiled,
+use cs39::{
+    run_demo,
+    cpu_stat,
+    compiler_stat,
+    cache_stat,
+    cpu_test,
+    size_test,
+    flag_test::{
+        self,
+        FlagConfig,
+        DEFAULT_FLAG_SETS,
+        parse_flag_sets,
     },
+    kernel_sum_test,
+    sweep::{self, parse_params},
+    compare,
+    diff::{self, DEFAULT_CONTEXT},
+    baseline::{self, CompareOptions, DEFAULT_BASELINE},
     navigate::{
         DemoLookup,
+        VersionQuery,
         demo_lookup,
-    },
-    quant::{
-        subproc,
-        demo_min_time,
+        for_each_version,
+        query_versions,
+        repo_dir,
     },
     output::{
-        Indent, 
-        INFO_INDENT,
         TableWriter,
         csv_path,
     },
+    cli::{
+        self,
+        Invocation,
+        Manual,
+        Matches,
+        CommandDef,
+    },
+    compile::{
+        Profile,
+        CompilerSource,
+        select_compiler,
+        prebuild,
+        BuildOptions,
+    },
+    quant::{BenchOptions, DEFAULT_WARMUP},
+    stats::SampleRow,
+    cache::clean,
+    metadata::Metadata,
+    config::load_config,
+    error::{Error, Result},
 };
 use std::{
     env::args,
-    process::Command,
-    path::PathBuf,
-    str::FromStr,
+    process::{Command, exit},
 };
-use serde::Serialize;
-
-/// C++ compilation.
-pub mod compile;
-
-/// Code demo navigation.
-pub mod navigate;
-
-/// Program output helpers.
-pub mod output;
-
-/// `size_test` task.
-pub mod size_test;
-
-/// Extraction of quantitative data from demos.
-pub mod quant;
-
-/// `kernel_sum_test` task.
-pub mod kernel_sum_test;
-
-/// Extract and parse a regex capture group.
-pub fn cap_parse<T: FromStr>(cap: &regex::Captures, group: &str) -> Option<T> {
-    cap.name(group).and_then(|m| m.as_str().parse().ok())
-}
-
-/// `run` task.
-pub fn run_demo(lookup: &DemoLookup, major: u32, minor: u32) -> Result<(), ()> {
-    let Compiled { workdir, binary } = compile(lookup, major, minor)?;
-    
-    println!("[INFO] running");
-    println!();
-    let status = Command::new(&binary)
-        .current_dir(&workdir)
-        .status().unwrap();
-    println!();
-    println!("[INFO] exit {}", status.code().unwrap());
-    
-    Ok(())
-}
-
-/// `stat` task/subtask.
-fn cpu_stat() {
-    println!("[INFO] cpu info:");
-    println!("{}", Indent(INFO_INDENT, ""));
-    println!("{}", Indent(INFO_INDENT, 
-        format_args!("LOGICAL CPUS = {}", num_cpus::get())));
-    println!("{}", Indent(INFO_INDENT,
-        format_args!("PHYSICAL CPUS = {}", num_cpus::get_physical())));
-    println!("{}", Indent(INFO_INDENT, ""));
-}
-
-#[derive(Clone, Debug, Serialize, PartialEq)]
-#[serde(rename_all = "PascalCase")]
-struct CpuTestRow {
-    threads: u32,
-    best_time_ms: f64,
-}
-
-/// `cpu_task` test.
-fn cpu_test(
-    lookup: &DemoLookup, 
-    major: u32, 
-    minor: u32, 
-    mut table: TableWriter<CpuTestRow>
-) -> Result<(), ()> {
-    let Compiled { workdir, binary } = compile(lookup, major, minor)?;
-    
-    cpu_stat();
-    
-    let min_cpu = 1;
-    let max_cpu = num_cpus::get();
-    
-    for cpu in min_cpu..=max_cpu {
-        println!("[INFO] benchmarking with {} thread", cpu);
-        let (status, lines) = subproc(
-            Command::new(&binary)
-                .current_dir(&workdir)
-                .env("OMP_NUM_THREADS", cpu.to_string()), false);
-            
-        let min_time = demo_min_time(&lines);
-        println!("[INFO] best time = {:.2}ms", min_time.as_secs_f64() / 1000.0);   
-        
-        table.write(CpuTestRow {
-            threads: cpu as _,
-            best_time_ms: min_time.as_secs_f64() / 1000.0
-        });
-         
-        println!();
-        if !status.success() {
-            println!("[ERROR] exit code {}", status.code().unwrap());
-            return Err(());
-        }
-    }
-    
-    println!("[INFO] done");
-    
-    Ok(())
-} 
 
 /// `reinstall` subtask.
-pub fn reinstall() {
+pub fn reinstall() -> Result<()> {
     println!("[INFO] recompiling cs39 cli");
-    let _ = Command::new("cargo")
+    let status = Command::new("cargo")
         .arg("install")
         .arg("--path")
         .arg(env!("CARGO_MANIFEST_DIR"))
         .arg("--force")
-        .status();
+        .status()
+        .map_err(Error::spawn("cargo"))?;
     println!();
+    if !status.success() {
+        return Err(Error::ReinstallFailed { status });
+    }
+    Ok(())
 }
 
-/// CLI parsing helper.
-pub fn get_version(args: &[String]) -> (u32, u32) {
-    assert!(args.len() >= 4, "unexpected num of args");
-
-    let major: u32 = args[2].parse().unwrap();
-    let minor: u32 = args[3].parse().unwrap();
+/// Parse the `MAJOR MINOR` positionals.
+pub fn get_version(matches: &Matches) -> Result<(u32, u32)> {
+    let major: u32 = matches.required("MAJOR")?;
+    let minor: u32 = matches.required("MINOR")?;
     
-    (major, minor)
+    Ok((major, minor))
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-pub enum VersionQuery {
-    Version(u32, u32),
-    AllInMajor(u32),
+/// Parse the `MAJOR MINOR` positionals, where `MINOR` may be `all`.
+pub fn get_version_query(matches: &Matches) -> Result<VersionQuery> {
+    let major: u32 = matches.required("MAJOR")?;
+    if matches.raw("MINOR") == Some("all") {
+        Ok(VersionQuery::AllInMajor(major))
+    } else {       
+        let minor: u32 = matches.required("MINOR")?;
+        Ok(VersionQuery::Version(major, minor))
+    }
 }
 
-pub fn get_version_query(args: &[String]) -> VersionQuery {
-    assert!(args.len() >= 4, "unexpected num of args");
+/// Choose the compiler, honoring the `--compiler` option.
+pub fn get_compiler(matches: &Matches) -> Result<Profile> {
+    let config = load_config()?;
+    let (profile, source) = select_compiler(matches.raw("compiler"), &config)?;
+    println!("[INFO] using compiler {} ({})", profile.name, match source {
+        CompilerSource::Flag => "from --compiler",
+        CompilerSource::Env => "from CS39_COMPILER",
+        CompilerSource::Config => "from config file",
+        CompilerSource::Detected => "detected",
+    });
+    Ok(profile)
+}
 
-    let major: u32 = args[2].parse().unwrap();
-    if args[3] == "all" {
-        VersionQuery::AllInMajor(major)
-    } else {       
-        let minor: u32 = args[3].parse().unwrap();
-        VersionQuery::Version(major, minor)
+/// Parse the `--jobs` option, defaulting to the number of logical CPUs, and
+/// the `--keep-build` and `--inject-defines` flags.
+pub fn get_build_options(matches: &Matches) -> Result<BuildOptions> {
+    let jobs: usize = matches.value("jobs")?.unwrap_or_else(num_cpus::get);
+    if jobs == 0 {
+        return Err(matches.invalid("jobs").into());
     }
+    Ok(BuildOptions {
+        jobs,
+        keep_staging: matches.flag("keep-build"),
+        inject_defines: matches.flag("inject-defines"),
+    })
 }
 
-fn main() {
-    let args: Vec<String> = args().collect();
-    
-    let repo = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .canonicalize().unwrap()
-        .parent().map(PathBuf::from).unwrap();
-        
-    let lookup = demo_lookup(&repo);
-    
-    match args[1].as_str() {
-        "help" => {
-            println!(include_str!("../manual.txt"));
-        },
+/// Parse the `--perf` flag and `--warmup` option, to run demos with
+/// `threads` as `OMP_NUM_THREADS`.
+pub fn get_bench_options(matches: &Matches, threads: Option<u32>) -> Result<BenchOptions> {
+    Ok(BenchOptions {
+        threads,
+        perf: matches.flag("perf"),
+        warmup: matches.value("warmup")?.unwrap_or(DEFAULT_WARMUP),
+        ..BenchOptions::default()
+    })
+}
+
+/// Parse the `--repeat` option and `--interleave` flag, on top of
+/// `get_bench_options`.
+pub fn get_repeat_options(matches: &Matches, bench: BenchOptions) -> Result<BenchOptions> {
+    let repeat: usize = matches.value("repeat")?.unwrap_or(1);
+    if repeat == 0 {
+        return Err(matches.invalid("repeat").into());
+    }
+    Ok(BenchOptions {
+        repeat,
+        interleave: matches.flag("interleave"),
+        ..bench
+    })
+}
+
+/// Open the raw samples table accompanying the output table `name`.
+pub fn samples_table(name: &str, metadata: &Metadata) -> Result<TableWriter<SampleRow>> {
+    TableWriter::csv_file(csv_path(format!("{}_samples.csv", name))?)?
+        .with_metadata(metadata)
+}
+
+/// Execute a parsed subcommand.
+fn dispatch(
+    lookup: &DemoLookup, 
+    matches: &Matches
+) -> Result<()> {
+    match matches.command.name {
         "list" => {
             println!("[INFO] listing demos");
             println!("{:#?}", lookup);
         },
         "reinstall" => {
-            reinstall();
+            reinstall()?;
         },
         "stat" => {
             cpu_stat();
+            compiler_stat(&load_config()?);
+            cache_stat()?;
+        },
+        "clean" => {
+            let removed = clean(matches.flag("all"))?;
+            println!("[INFO] removed staging dirs: {}, builds: {}, object files: {}",
+                removed.staging, removed.builds, removed.objects);
+            cache_stat()?;
         },
         "run" => {
-            let (major, minor) = get_version(&args);
-            let _ = run_demo(&lookup, major, minor);
+            let (major, minor) = get_version(matches)?;
+            let profile = get_compiler(matches)?;
+            run_demo(lookup, &profile, major, minor)?;
+        },
+        "cpu_test" => {
+            let query = get_version_query(matches)?;
+            let min_threads: u32 = matches.value("min-threads")?.unwrap_or(1);
+            let max_threads: u32 = matches.value("max-threads")?
+                .unwrap_or(num_cpus::get() as u32);
+            if min_threads == 0 {
+                return Err(matches.invalid("min-threads").into());
+            }
+            if max_threads < min_threads {
+                return Err(matches.invalid("max-threads").into());
+            }
+            let options = get_build_options(matches)?;
+            let bench = get_repeat_options(matches, 
+                get_bench_options(matches, None)?)?;
+            let profile = get_compiler(matches)?;
+            let metadata = Metadata::collect(std::slice::from_ref(&profile));
+            prebuild(lookup, query, std::slice::from_ref(&profile), &options)?;
+            
+            for_each_version(lookup, query, |major, minor| {
+                let name = format!("cpu_test_{}_{}", major, minor);
+                let table = TableWriter::csv_file_with_header(
+                    csv_path(format!("{}.csv", name))?, cpu_test::header(bench.perf))?
+                    .with_metadata(&metadata)?;
+                let samples = samples_table(&name, &metadata)?;
+            
+                let threads = min_threads..=max_threads;
+                cpu_test::run(lookup, &profile, major, minor, threads, &bench, 
+                    table, samples)
+            })?;
         },
-        "cpu_test" => match get_version_query(&args) {
-            VersionQuery::Version(major, minor) => {
-                let csv_name = format!("cpu_test_{}_{}.csv", major, minor);
-                let table = TableWriter::csv_file(csv_path(&csv_name));
+        "size_test" => {
+            let query = get_version_query(matches)?;
+            let options = get_build_options(matches)?;
+            let bench = get_repeat_options(matches, 
+                get_bench_options(matches, None)?)?;
+            let profile = get_compiler(matches)?;
+            let metadata = Metadata::collect(std::slice::from_ref(&profile));
             
-                let _ = cpu_test(&lookup, major, minor, table);
-            },
-            VersionQuery::AllInMajor(major) => {
-                for &minor in lookup[&major].demos.keys() {
-                    println!("[INFO] testing demo {}-{}", major, minor);
-                    let csv_name = format!("cpu_test_{}_{}.csv", major, minor);
-                    let table = TableWriter::csv_file(csv_path(&csv_name));
+            for_each_version(lookup, query, |major, minor| {
+                let name = format!("size_test_{}_{}", major, minor);
+                let dims = size_test::find_dims(lookup, major, minor)?;
+                let table = TableWriter::csv_file_with_header(
+                    csv_path(format!("{}.csv", name))?, size_test::header(&dims, bench.perf))?
+                    .with_metadata(&metadata)?;
+                let samples = samples_table(&name, &metadata)?;
             
-                    let _ = cpu_test(&lookup, major, minor, table);
-                }
-            }
+                size_test::run(lookup, &profile, major, minor, &options, &bench, 
+                    table, samples)
+            })?;
         },
-        "size_test" => match get_version_query(&args) {
-            VersionQuery::Version(major, minor) => {
-                let csv_name = format!("size_test_{}_{}.csv", major, minor);
-                let table = TableWriter::csv_file(csv_path(&csv_name));
+        "flag_test" => {
+            let query = get_version_query(matches)?;
+            let bench = get_bench_options(matches, matches.value("threads")?)?;
+            let options = get_build_options(matches)?;
+            let flag_sets = match matches.raw("flags") {
+                Some(sets) => parse_flag_sets(sets),
+                None => parse_flag_sets(&DEFAULT_FLAG_SETS.join(";")),
+            };
+            let profiles = match matches.raw("profiles") {
+                Some(names) => {
+                    let config = load_config()?;
+                    names.split(',')
+                        .map(|name| config.profile(name.trim()))
+                        .collect::<Result<Vec<Profile>>>()?
+                },
+                None => vec![get_compiler(matches)?],
+            };
+            let configs = FlagConfig::matrix(&profiles, &flag_sets);
+            let metadata = Metadata::collect(&configs.iter()
+                .map(FlagConfig::effective_profile)
+                .collect::<Vec<_>>());
             
-                let _ = size_test::run(&repo, &lookup, major, minor, table);
-            },
-            VersionQuery::AllInMajor(major) => {
-                for &minor in lookup[&major].demos.keys() {
-                    println!("[INFO] testing demo {}-{}", major, minor);
-                    let csv_name = format!("size_test_{}_{}.csv", major, minor);
-                    let table = TableWriter::csv_file(csv_path(&csv_name));
+            for_each_version(lookup, query, |major, minor| {
+                let name = format!("flag_test_{}_{}", major, minor);
+                let table = TableWriter::csv_file_with_header(
+                    csv_path(format!("{}.csv", name))?, flag_test::header(bench.perf))?
+                    .with_metadata(&metadata)?;
+                let samples = samples_table(&name, &metadata)?;
+                
+                flag_test::run(lookup, &configs, major, minor, &options, &bench, 
+                    table, samples)
+            })?;
+        },
+        "sweep" => {
+            let query = get_version_query(matches)?;
+            let params = match matches.raw("params") {
+                Some(specs) => parse_params(specs)
+                    .ok_or_else(|| matches.invalid("params"))?,
+                None => {
+                    return for_each_version(lookup, query, |major, minor| 
+                        sweep::list(lookup, major, minor));
+                },
+            };
+            let bench = get_bench_options(matches, matches.value("threads")?)?;
+            let options = get_build_options(matches)?;
+            let profile = get_compiler(matches)?;
+            let metadata = Metadata::collect(std::slice::from_ref(&profile));
             
-                    let _ = size_test::run(&repo, &lookup, major, minor, table);
-                }
+            for_each_version(lookup, query, |major, minor| {
+                let name = format!("sweep_{}_{}", major, minor);
+                let table = TableWriter::csv_file_with_header(
+                    csv_path(format!("{}.csv", name))?, sweep::header(&params, bench.perf))?
+                    .with_metadata(&metadata)?;
+                let samples = samples_table(&name, &metadata)?;
+                
+                sweep::run(lookup, &profile, major, minor, &params, &options, 
+                    &bench, table, samples)
+            })?;
+        },
+        "compare" => {
+            let major: u32 = matches.required("MAJOR")?;
+            let minors: Vec<u32> = match matches.raw("minors") {
+                Some(list) => list.split(',')
+                    .map(|minor| minor.trim().parse())
+                    .collect::<std::result::Result<_, _>>()
+                    .map_err(|_| matches.invalid("minors"))?,
+                None => query_versions(lookup, VersionQuery::AllInMajor(major))?
+                    .into_iter()
+                    .map(|(_, minor)| minor)
+                    .collect(),
+            };
+            let distinct = minors.iter()
+                .enumerate()
+                .all(|(i, minor)| !minors[..i].contains(minor));
+            if minors.is_empty() || !distinct {
+                return Err(matches.invalid("minors").into());
+            }
+            let reference: u32 = matches.value("reference")?.unwrap_or(minors[0]);
+            if !minors.contains(&reference) {
+                return Err(matches.invalid("reference").into());
             }
+            let bench = get_bench_options(matches, matches.value("threads")?)?;
+            let bench = get_repeat_options(matches, bench)?;
+            let options = get_build_options(matches)?;
+            let profile = get_compiler(matches)?;
+            let metadata = Metadata::collect(std::slice::from_ref(&profile));
+            let name = format!("compare_{}", major);
+            let table = TableWriter::csv_file_with_header(
+                csv_path(format!("{}.csv", name))?, compare::header(bench.perf))?
+                .with_metadata(&metadata)?;
+            let samples = samples_table(&name, &metadata)?;
+            
+            compare::run(lookup, &profile, major, &minors, reference, &options, 
+                &bench, table, samples)?;
         },
-        "kernel_sum_test" => {
-            let version = (1, 2);
-            println!("[INFO] implicitly testing demo {:?}", version);
+        "diff" => {
+            let major: u32 = matches.required("MAJOR")?;
+            let a: u32 = matches.required("A")?;
+            let b: u32 = matches.required("B")?;
+            let context: usize = matches.value("context")?.unwrap_or(DEFAULT_CONTEXT);
             
-            let multithreaded: bool = args[2].parse().unwrap();
+            diff::run(lookup, major, a, b, context, matches.flag("timing"))?;
+        },
+        "kernel_sum_test" => {
+            let multithreaded = matches.flag("multithreaded");
+            let major: u32 = matches.value("major")?.unwrap_or(1);
+            let minor: u32 = matches.value("minor")?.unwrap_or(2);
+            let profile = get_compiler(matches)?;
+            let csv_name = format!("kernel_sum_test_{}_{}.csv", major, minor);
+            let table = TableWriter::csv_file_with_header(
+                csv_path(&csv_name)?, kernel_sum_test::header())?
+                .with_metadata(&Metadata::collect(std::slice::from_ref(&profile)))?;
             
-            let (major, minor) = version;
+            kernel_sum_test::run(lookup, &profile, major, minor, multithreaded, 
+                table)?;
+        },
+        "baseline" => {
+            let query = get_version_query(matches)?;
+            let name = matches.raw("name").unwrap_or(DEFAULT_BASELINE);
+            let valid_name = !name.starts_with('.') && name.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+            if !valid_name {
+                return Err(matches.invalid("name").into());
+            }
+            let tasks: Vec<&str> = match matches.raw("task") {
+                Some(task) => baseline::TASKS.iter()
+                    .filter(|&&known| known == task)
+                    .copied()
+                    .collect(),
+                None => baseline::TASKS.to_vec(),
+            };
+            if tasks.is_empty() {
+                return Err(matches.invalid("task").into());
+            }
             
-            let _ = kernel_sum_test::run(&lookup, major, minor, multithreaded);
-        }
-        _ => {
-            println!(include_str!("../manual.txt"));
+            match matches.raw("ACTION") {
+                Some("save") => for_each_version(lookup, query, |major, minor|
+                    baseline::save(&tasks, name, major, minor))?,
+                Some("compare") => {
+                    let confidence: f64 = matches.value("confidence")?.unwrap_or(95.0);
+                    if !(confidence > 0.0 && confidence < 100.0) {
+                        return Err(matches.invalid("confidence").into());
+                    }
+                    let threshold: f64 = matches.value("threshold")?.unwrap_or(2.0);
+                    if threshold.is_nan() || threshold < 0.0 {
+                        return Err(matches.invalid("threshold").into());
+                    }
+                    let options = CompareOptions {
+                        confidence: confidence / 100.0,
+                        threshold: threshold / 100.0,
+                    };
+                    let metadata = Metadata::collect(&[]);
+                    
+                    for_each_version(lookup, query, |major, minor| {
+                        let csv_name = format!("baseline_compare_{}_{}.csv", major, minor);
+                        let table = TableWriter::csv_file(csv_path(&csv_name)?)?
+                            .with_metadata(&metadata)?;
+                        
+                        baseline::compare(&tasks, name, major, minor, &options, table)
+                    })?;
+                },
+                _ => return Err(matches.invalid("ACTION").into()),
+            }
+        },
+        name => unreachable!("unhandled subcommand {:?}", name),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = args().skip(1).collect();
+    
+    let matches = match cli::parse(&args) {
+        Ok(Invocation::Command(matches)) => matches,
+        Ok(Invocation::Help(None)) => {
+            println!("{}", Manual);
+            return;
+        },
+        Ok(Invocation::Help(Some(command))) => {
+            println!("{}", command);
+            return;
+        },
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            print_usage_hint(e.command());
+            exit(2);
+        },
+    };
+    
+    let result = repo_dir()
+        .and_then(|repo| demo_lookup(&repo)
+            .and_then(|lookup| dispatch(&lookup, &matches)));
+    match result {
+        Ok(()) => (),
+        Err(Error::Cli(e)) => {
+            eprintln!("[ERROR] {}", e);
+            print_usage_hint(e.command());
+            exit(2);
+        },
+        Err(e) => {
+            eprintln!();
+            eprintln!("[ERROR] {}", e);
+            exit(1);
+        },
+    }
+}
+
+/// Point the user towards the relevant help page after a CLI error.
+fn print_usage_hint(command: Option<&CommandDef>) {
+    match command {
+        Some(command) => {
+            eprintln!("        usage: {}", command.usage());
+            eprintln!("        see `cs39 help {}`", command.name);
+        },
+        None => {
+            eprintln!("        see `cs39 help`");
         },
     }
 }
@@ -0,0 +1,253 @@
+use crate::{
+    compile::Profile,
+    hardware::CpuInfo,
+    navigate::repo_dir,
+    error::{Error, Result},
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+    fs::write,
+    env,
+};
+use serde::Serialize;
+
+/// Where an output table came from: the machine, compilers, and source tree
+/// that produced it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Metadata {
+    /// When the task started, in UTC, as RFC 3339.
+    pub timestamp: String,
+    pub cs39_version: String,
+    /// Command line, eg. `cs39 size_test 0 3`.
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// OS name and release, eg. `Linux 6.1.0`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_model: Option<String>,
+    pub logical_cpus: usize,
+    pub physical_cpus: usize,
+    /// Commit of the demos repository checkout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    /// Whether the checkout had uncommitted changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_dirty: Option<bool>,
+    pub compilers: Vec<CompilerMetadata>,
+}
+
+/// A compiler profile used to produce an output table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompilerMetadata {
+    pub profile: String,
+    pub program: String,
+    /// First line of `program --version`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub compile_flags: Vec<String>,
+    pub link_flags: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub defines: BTreeMap<String, String>,
+}
+
+impl CompilerMetadata {
+    /// Describe a profile, asking the compiler its version.
+    ///
+    /// Env var references are expanded as they are when compiling, or left
+    /// as they are if they can't be.
+    pub fn collect(profile: &Profile) -> Self {
+        let expand = |s: &String| profile.expand(s).unwrap_or_else(|_| s.clone());
+        let version = profile.command()
+            .ok()
+            .and_then(|mut cmd| cmd.arg("--version").output().ok())
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .map(|line| line.trim().to_owned()));
+        CompilerMetadata {
+            profile: profile.name.clone(),
+            program: expand(&profile.program),
+            version,
+            compile_flags: profile.compile_flags.iter().map(expand).collect(),
+            link_flags: profile.link_flags.iter().map(expand).collect(),
+            defines: profile.defines.iter()
+                .map(|(name, value)| (name.clone(), expand(value)))
+                .collect(),
+        }
+    }
+}
+
+impl Metadata {
+    /// Fingerprint this machine and checkout, for tables produced with some
+    /// compiler profiles.
+    pub fn collect(profiles: &[Profile]) -> Self {
+        let cpu = CpuInfo::detect();
+        let repo = repo_dir().ok();
+        let git = |args: &[&str]| repo.as_ref()
+            .and_then(|repo| Command::new("git")
+                .args(args)
+                .current_dir(repo)
+                .output()
+                .ok())
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned());
+        let mut compilers: Vec<CompilerMetadata> = Vec::new();
+        for profile in profiles {
+            let compiler = CompilerMetadata::collect(profile);
+            if !compilers.contains(&compiler) {
+                compilers.push(compiler);
+            }
+        }
+
+        Metadata {
+            timestamp: rfc3339_utc(SystemTime::now()),
+            cs39_version: env!("CARGO_PKG_VERSION").to_owned(),
+            command: Some("cs39".to_owned()).into_iter()
+                .chain(env::args().skip(1))
+                .collect::<Vec<_>>()
+                .join(" "),
+            hostname: hostname(),
+            kernel: kernel(),
+            cpu_model: cpu.model,
+            logical_cpus: num_cpus::get(),
+            physical_cpus: num_cpus::get_physical(),
+            git_commit: git(&["rev-parse", "HEAD"]),
+            git_dirty: git(&["status", "--porcelain", "--untracked-files=no"])
+                .map(|status| !status.is_empty()),
+            compilers,
+        }
+    }
+
+    /// Write as TOML to the sidecar file of an output table.
+    pub fn write_sidecar<P: AsRef<Path>>(&self, table: P) -> Result<PathBuf> {
+        let path = sidecar_path(table);
+        let text = toml::to_string(self)
+            .expect("metadata is always representable as TOML");
+        write(&path, text).map_err(Error::io(&path))?;
+        Ok(path)
+    }
+}
+
+/// Path of the metadata sidecar file of an output table, eg.
+/// `cpu_test_0_3.meta.toml` for `cpu_test_0_3.csv`.
+pub fn sidecar_path<P: AsRef<Path>>(table: P) -> PathBuf {
+    table.as_ref().with_extension("meta.toml")
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the length passed is the length of the buffer
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(unix)]
+fn kernel() -> Option<String> {
+    use std::ffi::CStr;
+
+    // SAFETY: utsname is plain data, and uname fills it with nul-terminated
+    // strings on success
+    unsafe {
+        let mut uts: libc::utsname = std::mem::zeroed();
+        if libc::uname(&mut uts) != 0 {
+            return None;
+        }
+        let field = |chars: &[libc::c_char]| CStr::from_ptr(chars.as_ptr())
+            .to_string_lossy()
+            .into_owned();
+        Some(format!("{} {}", field(&uts.sysname), field(&uts.release)))
+    }
+}
+
+#[cfg(not(unix))]
+fn kernel() -> Option<String> {
+    Some(env::consts::OS.to_owned())
+}
+
+/// Format a time as RFC 3339 in UTC, to the second, eg.
+/// `2020-03-14T15:09:26Z`.
+pub fn rfc3339_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // days since the epoch to a proleptic gregorian date, after
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day,
+        secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rfc3339() {
+        let at = |secs| rfc3339_utc(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(1584198566), "2020-03-14T15:09:26Z");
+        // leap day, and the last second of a leap year
+        assert_eq!(at(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1609459199), "2020-12-31T23:59:59Z");
+        assert_eq!(at(4102444800), "2100-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn compiler_metadata_expands_env() {
+        let profile = Profile {
+            name: "custom".to_owned(),
+            program: "${BIN_DIR}/g++".to_owned(),
+            compile_flags: vec!["-O${OPT_LEVEL}".to_owned(), "-w".to_owned()],
+            link_flags: vec!["-L${BIN_DIR}/../lib".to_owned()],
+            env: [("BIN_DIR", "/nonexistent/bin"), ("OPT_LEVEL", "3")].iter()
+                .map(|&(key, val)| (key.to_owned(), val.to_owned()))
+                .collect(),
+            defines: Some(("LEVEL".to_owned(), "${OPT_LEVEL}".to_owned()))
+                .into_iter()
+                .collect(),
+            ..Profile::default()
+        };
+        let metadata = CompilerMetadata::collect(&profile);
+        assert_eq!(metadata.program, "/nonexistent/bin/g++");
+        assert_eq!(metadata.version, None);
+        assert_eq!(metadata.compile_flags, ["-O3", "-w"]);
+        assert_eq!(metadata.link_flags, ["-L/nonexistent/bin/../lib"]);
+        assert_eq!(metadata.defines["LEVEL"], "3");
+
+        let missing = Profile {
+            compile_flags: vec!["-I${CS39_TEST_UNSET_VAR}".to_owned()],
+            ..profile
+        };
+        assert_eq!(CompilerMetadata::collect(&missing).compile_flags,
+            ["-I${CS39_TEST_UNSET_VAR}"]);
+    }
+}
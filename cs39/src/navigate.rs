@@ -1,5 +1,8 @@
 
-use crate::cap_parse;
+use crate::{
+    cap_parse,
+    error::{Error, Result},
+};
 use std::{
     path::{Path, PathBuf},
     collections::BTreeMap,
@@ -20,14 +23,14 @@ pub struct Subdir {
 pub type DemoLookup = BTreeMap::<u32, Subdir>;
 
 /// Read the demo directory structure.
-pub fn demo_lookup<P: AsRef<Path>>(repo: P) -> DemoLookup {
+pub fn demo_lookup<P: AsRef<Path>>(repo: P) -> Result<DemoLookup> {
     let pat = r#"^[_[[:alnum:]]]+_(?P<major>\d+)_(?P<minor>\d+)$"#;
     let pat = Regex::new(pat).unwrap();
     
     let mut lookup = DemoLookup::new();
     
-    for subdir in subdirs(&repo) {
-        let mut demos: Vec<(PathBuf, (u32, u32))> = subdirs(&subdir)
+    for subdir in subdirs(&repo)? {
+        let mut demos: Vec<(PathBuf, (u32, u32))> = subdirs(&subdir)?
             .filter_map(|p| p.file_stem()
                 .and_then(OsStr::to_str)
                 .and_then(|s| pat.captures(s))
@@ -46,7 +49,7 @@ pub fn demo_lookup<P: AsRef<Path>>(repo: P) -> DemoLookup {
             .collect();
         majors.dedup();
 
-        if majors.len() == 0 { continue; }
+        if majors.is_empty() { continue; }
         if majors.len() > 1 {
             eprintln!("[WARN] several major versions detected in {:?}", subdir);
             continue;
@@ -56,7 +59,7 @@ pub fn demo_lookup<P: AsRef<Path>>(repo: P) -> DemoLookup {
             eprintln!(
                 "[WARN] conflicting major version {} between {:?} and {:?}",
                 major, subdir, conflict.subdir_path);
-            if !(demos.len() > conflict.demos.len()) {
+            if demos.len() <= conflict.demos.len() {
                 continue;
             }
         }
@@ -69,7 +72,15 @@ pub fn demo_lookup<P: AsRef<Path>>(repo: P) -> DemoLookup {
         });
     }
     
-    lookup
+    Ok(lookup)
+}
+
+/// Path to the root of the demos repository.
+pub fn repo_dir() -> Result<PathBuf> {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let manifest = manifest.canonicalize()
+        .map_err(Error::io(manifest))?;
+    Ok(manifest.parent().map(PathBuf::from).unwrap_or(manifest))
 }
 
 /// Find a path for a demo by number.
@@ -77,30 +88,35 @@ pub fn find_demo(
     lookup: &DemoLookup, 
     major: u32, 
     minor: u32
-) -> Result<PathBuf, ()> {
-    let subdir = lookup.get(&major)
-        .ok_or_else(|| {
-            eprintln!("[ERROR] major version {} not found", major);
-            eprintln!("        available: {:?}", 
-                lookup.keys().copied().collect::<Vec<u32>>());
-        })?;
+) -> Result<PathBuf> {
+    let subdir = find_major(lookup, major)?;
     let path = subdir.demos.get(&minor)
-        .ok_or_else(|| {
-            eprintln!("[ERROR] minor version {} not found in {:?}", 
-                minor, subdir.subdir_path);
-            eprintln!("        available: {:?}",
-                subdir.demos.keys().copied().collect::<Vec<u32>>());
+        .ok_or_else(|| Error::MinorNotFound {
+            major,
+            minor,
+            subdir: subdir.subdir_path.clone(),
+            available: subdir.demos.keys().copied().collect(),
         })?;
     Ok(path.clone())
 }
 
+/// Find the subdirectory for a major version.
+pub fn find_major(lookup: &DemoLookup, major: u32) -> Result<&Subdir> {
+    lookup.get(&major)
+        .ok_or_else(|| Error::MajorNotFound {
+            major,
+            available: lookup.keys().copied().collect(),
+        })
+}
+
 
 /// List direct sub-**directories** of a directory.
 ///
-/// Filters to not being with '.'.
-pub fn subdirs<P: AsRef<Path>>(path: P) -> impl Iterator<Item=PathBuf> {
-    read_dir(path).unwrap()
-        .filter_map(Result::ok)
+/// Filters to not begin with '.'.
+pub fn subdirs<P: AsRef<Path>>(path: P) -> Result<impl Iterator<Item=PathBuf>> {
+    let path = path.as_ref();
+    Ok(read_dir(path).map_err(Error::io(path))?
+        .filter_map(|entry| entry.ok())
         .filter_map(|f| f.file_type().ok()
             .filter(FileType::is_dir)
             .map(|_| f.path())
@@ -108,5 +124,60 @@ pub fn subdirs<P: AsRef<Path>>(path: P) -> impl Iterator<Item=PathBuf> {
             .and_then(OsStr::to_str)
             .and_then(|s| s.chars().next())
             .map(|c| c != '.')
-            .unwrap_or(false)))
+            .unwrap_or(false))))
+}
+
+/// Single demo, or every demo within a major version.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum VersionQuery {
+    Version(u32, u32),
+    AllInMajor(u32),
+}
+
+/// Resolve a version query to the list of demos it covers.
+pub fn query_versions(
+    lookup: &DemoLookup, 
+    query: VersionQuery
+) -> Result<Vec<(u32, u32)>> {
+    match query {
+        VersionQuery::Version(major, minor) => Ok(vec![(major, minor)]),
+        VersionQuery::AllInMajor(major) => Ok(find_major(lookup, major)?
+            .demos.keys()
+            .map(|&minor| (major, minor))
+            .collect()),
+    }
+}
+
+/// Run a task for each demo in a query, continuing past failures.
+pub fn for_each_version<F>(
+    lookup: &DemoLookup, 
+    query: VersionQuery, 
+    mut task: F
+) -> Result<()>
+where
+    F: FnMut(u32, u32) -> Result<()>,
+{
+    let versions = query_versions(lookup, query)?;
+    if let VersionQuery::Version(..) = query {
+        let (major, minor) = versions[0];
+        return task(major, minor);
+    }
+    
+    let mut failed = Vec::new();
+    for &(major, minor) in &versions {
+        println!("[INFO] testing demo {}-{}", major, minor);
+        if let Err(e) = task(major, minor) {
+            eprintln!("[ERROR] {}", e);
+            eprintln!();
+            failed.push((major, minor));
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::BatchFailed {
+            failed,
+            total: versions.len(),
+        })
+    }
 }
@@ -8,8 +8,16 @@ use std::{
     },
     marker::PhantomData,
 };
-use csv::Writer as CsvWriter;
+use csv::{
+    Writer as CsvWriter,
+    WriterBuilder as CsvWriterBuilder,
+};
 use serde::Serialize;
+use crate::{
+    navigate::repo_dir,
+    metadata::Metadata,
+    error::{Error, Result},
+};
 
 pub struct Indent<'a, I: Display>(pub &'a str, pub I);
 
@@ -30,22 +38,17 @@ impl<'a, I: Display> Display for Indent<'a, I> {
     }
 }
 
-pub const INFO_INDENT: &'static str = "       ";
+pub const INFO_INDENT: &str = "       ";
 
 /// Allocate a path for a CSV file.
-pub fn csv_path<S>(name: S) -> PathBuf 
+pub fn csv_path<S>(name: S) -> Result<PathBuf>
 where
     S: AsRef<str> 
 {
-    // unpleasent redundancy, but whatevs.
-    let repo = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .canonicalize().unwrap()
-        .parent().map(PathBuf::from).unwrap();
-    
-    let host = repo.join("output");
-    create_dir_all(&host).unwrap();
+    let host = repo_dir()?.join("output");
+    create_dir_all(&host).map_err(Error::io(&host))?;
     
-    host.join(name.as_ref())
+    Ok(host.join(name.as_ref()))
 }
 
 /// Output data table writer.
@@ -57,7 +60,10 @@ pub struct TableWriter<T: Serialize> {
 
 enum TableTarget {
     None,
-    Csv(CsvWriter<File>),
+    Csv {
+        path: PathBuf,
+        csv: Box<CsvWriter<File>>,
+    },
 }
 
 impl<T: Serialize> TableWriter<T> {
@@ -72,22 +78,62 @@ impl<T: Serialize> TableWriter<T> {
         Self::from(TableTarget::None)
     }
     
-    pub fn csv_file<P>(path: P) -> Self 
+    pub fn csv_file<P>(path: P) -> Result<Self>
     where 
         P: AsRef<Path>
     {
-        println!("[INFO] writing csv file to {:?}", path.as_ref());
-        let csv = CsvWriter::from_path(&path).unwrap();
-        Self::from(TableTarget::Csv(csv))
+        let path = path.as_ref().to_owned();
+        println!("[INFO] writing csv file to {:?}", path);
+        let csv = CsvWriter::from_path(&path)
+            .map_err(|source| Error::Csv { path: path.clone(), source })?;
+        Ok(Self::from(TableTarget::Csv {
+            path,
+            csv: Box::new(csv),
+        }))
+    }
+    
+    /// Write a CSV file with a header row decided at runtime, for rows 
+    /// which serialize as plain sequences.
+    pub fn csv_file_with_header<P, I, S>(path: P, header: I) -> Result<Self>
+    where 
+        P: AsRef<Path>,
+        I: IntoIterator<Item=S>,
+        S: AsRef<[u8]>,
+    {
+        let path = path.as_ref().to_owned();
+        println!("[INFO] writing csv file to {:?}", path);
+        let mut csv = CsvWriterBuilder::new()
+            .has_headers(false)
+            .from_path(&path)
+            .and_then(|mut csv| csv.write_record(header).map(|()| csv))
+            .map_err(|source| Error::Csv { path: path.clone(), source })?;
+        csv.flush().map_err(Error::io(&path))?;
+        Ok(Self::from(TableTarget::Csv {
+            path,
+            csv: Box::new(csv),
+        }))
+    }
+    
+    /// Record where the table came from, in a sidecar file next to it, as
+    /// CSV has nowhere to put it.
+    pub fn with_metadata(self, metadata: &Metadata) -> Result<Self> {
+        if let TableTarget::Csv { path, .. } = &self.target {
+            let sidecar = metadata.write_sidecar(path)?;
+            println!("[INFO] writing metadata to {:?}", sidecar);
+        }
+        Ok(self)
     }
     
-    pub fn write(&mut self, row: T) {
+    pub fn write(&mut self, row: T) -> Result<()> {
         match &mut self.target {
-            &mut TableTarget::None => (),
-            &mut TableTarget::Csv(ref mut csv) => {
-                csv.serialize(row).unwrap();
+            TableTarget::None => (),
+            TableTarget::Csv { path, csv } => {
+                csv.serialize(row)
+                    .and_then(|()| csv.flush().map_err(csv::Error::from))
+                    .map_err(|source| Error::Csv { path: path.clone(), source })?;
             },
         };
+        Ok(())
     }
 }
 
@@ -0,0 +1,303 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io,
+};
+use serde::ser::SerializeSeq;
+
+/// Hardware event counted by `PerfCounters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PerfEvent {
+    Cycles,
+    Instructions,
+    /// Usually last-level cache misses.
+    CacheMisses,
+    /// Last-level cache read accesses.
+    LlcLoads,
+    /// Last-level cache read accesses which missed.
+    LlcLoadMisses,
+    BranchMisses,
+}
+
+impl PerfEvent {
+    /// Every event, in the order of `PerfCounts` columns.
+    pub const ALL: [PerfEvent; 6] = [
+        PerfEvent::Cycles,
+        PerfEvent::Instructions,
+        PerfEvent::CacheMisses,
+        PerfEvent::LlcLoads,
+        PerfEvent::LlcLoadMisses,
+        PerfEvent::BranchMisses,
+    ];
+
+    /// `perf_event_attr` type and config values.
+    #[cfg(target_os = "linux")]
+    fn type_config(self) -> (u32, u64) {
+        const TYPE_HARDWARE: u32 = 0;
+        const TYPE_HW_CACHE: u32 = 3;
+        // cache id | op << 8 | result << 16
+        const LL_READ_ACCESS: u64 = 2;
+        const LL_READ_MISS: u64 = 2 | 1 << 16;
+        match self {
+            PerfEvent::Cycles => (TYPE_HARDWARE, 0),
+            PerfEvent::Instructions => (TYPE_HARDWARE, 1),
+            PerfEvent::CacheMisses => (TYPE_HARDWARE, 3),
+            PerfEvent::BranchMisses => (TYPE_HARDWARE, 5),
+            PerfEvent::LlcLoads => (TYPE_HW_CACHE, LL_READ_ACCESS),
+            PerfEvent::LlcLoadMisses => (TYPE_HW_CACHE, LL_READ_MISS),
+        }
+    }
+}
+
+impl Display for PerfEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PerfEvent::Cycles => "cycles",
+            PerfEvent::Instructions => "instructions",
+            PerfEvent::CacheMisses => "cache-misses",
+            PerfEvent::LlcLoads => "LLC-loads",
+            PerfEvent::LlcLoadMisses => "LLC-load-misses",
+            PerfEvent::BranchMisses => "branch-misses",
+        })
+    }
+}
+
+/// Counts of hardware events in a sub-process. Each is `None` if it
+/// couldn't be counted.
+///
+/// Counts are of user-space events only, and are scaled up if the kernel had
+/// to multiplex the counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PerfCounts {
+    pub cycles: Option<u64>,
+    pub instructions: Option<u64>,
+    pub cache_misses: Option<u64>,
+    pub llc_loads: Option<u64>,
+    pub llc_load_misses: Option<u64>,
+    pub branch_misses: Option<u64>,
+}
+
+impl PerfCounts {
+    /// Names of the table columns written by `serialize_columns`.
+    pub const COLUMNS: &'static [&'static str] = &[
+        "Cycles",
+        "Instructions",
+        "CacheMisses",
+        "LlcLoads",
+        "LlcLoadMisses",
+        "BranchMisses",
+        "Ipc",
+        "LlcMissRate",
+        "BranchMissesPerKiloInstr",
+    ];
+
+    /// Count of an event.
+    pub fn get(&self, event: PerfEvent) -> Option<u64> {
+        match event {
+            PerfEvent::Cycles => self.cycles,
+            PerfEvent::Instructions => self.instructions,
+            PerfEvent::CacheMisses => self.cache_misses,
+            PerfEvent::LlcLoads => self.llc_loads,
+            PerfEvent::LlcLoadMisses => self.llc_load_misses,
+            PerfEvent::BranchMisses => self.branch_misses,
+        }
+    }
+
+    fn get_mut(&mut self, event: PerfEvent) -> &mut Option<u64> {
+        match event {
+            PerfEvent::Cycles => &mut self.cycles,
+            PerfEvent::Instructions => &mut self.instructions,
+            PerfEvent::CacheMisses => &mut self.cache_misses,
+            PerfEvent::LlcLoads => &mut self.llc_loads,
+            PerfEvent::LlcLoadMisses => &mut self.llc_load_misses,
+            PerfEvent::BranchMisses => &mut self.branch_misses,
+        }
+    }
+
+    /// Mean counts of several runs, for events counted in all of them.
+    pub fn mean(runs: &[Self]) -> Self {
+        let mut mean = PerfCounts::default();
+        for event in PerfEvent::ALL {
+            let counts: Option<Vec<u64>> = runs.iter()
+                .map(|run| run.get(event))
+                .collect();
+            *mean.get_mut(event) = counts
+                .filter(|counts| !counts.is_empty())
+                .map(|counts| counts.iter().sum::<u64>() / counts.len() as u64);
+        }
+        mean
+    }
+
+    /// Instructions per cycle.
+    pub fn ipc(&self) -> Option<f64> {
+        ratio(self.instructions?, self.cycles?)
+    }
+
+    /// Fraction of last-level cache loads which missed.
+    pub fn llc_miss_rate(&self) -> Option<f64> {
+        ratio(self.llc_load_misses?, self.llc_loads?)
+    }
+
+    /// Branch misses per thousand instructions.
+    pub fn branch_mpki(&self) -> Option<f64> {
+        ratio(self.branch_misses?, self.instructions?).map(|r| r * 1000.0)
+    }
+
+    /// Serialize as consecutive columns of a table row, leaving missing
+    /// counts empty.
+    pub fn serialize_columns<S: SerializeSeq>(&self, seq: &mut S) -> Result<(), S::Error> {
+        seq.serialize_element(&self.cycles)?;
+        seq.serialize_element(&self.instructions)?;
+        seq.serialize_element(&self.cache_misses)?;
+        seq.serialize_element(&self.llc_loads)?;
+        seq.serialize_element(&self.llc_load_misses)?;
+        seq.serialize_element(&self.branch_misses)?;
+        seq.serialize_element(&self.ipc())?;
+        seq.serialize_element(&self.llc_miss_rate())?;
+        seq.serialize_element(&self.branch_mpki())?;
+        Ok(())
+    }
+}
+
+fn ratio(a: u64, b: u64) -> Option<f64> {
+    (b > 0).then(|| a as f64 / b as f64)
+}
+
+impl Display for PerfCounts {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let fmt_or_na = |value: Option<f64>, precision: usize| value
+            .map(|value| format!("{:.*}", precision, value))
+            .unwrap_or_else(|| "n/a".to_owned());
+        write!(f, "IPC {}, LLC miss rate {}, {} branch misses per 1k instructions",
+            fmt_or_na(self.ipc(), 2),
+            self.llc_miss_rate()
+                .map(|rate| format!("{:.1}%", rate * 100.0))
+                .unwrap_or_else(|| "n/a".to_owned()),
+            fmt_or_na(self.branch_mpki(), 2))
+    }
+}
+
+/// Hardware event counters for the sub-processes the current thread spawns.
+///
+/// The counters are opened disabled on the current thread, are inherited by
+/// the processes and threads it spawns afterwards, and start counting in each
+/// when it calls `exec`. So, to count events in a single sub-process, open
+/// them just before spawning it, and read them once it's been waited for.
+/// Only works on Linux.
+#[derive(Debug)]
+pub struct PerfCounters {
+    counters: Vec<(PerfEvent, File)>,
+}
+
+impl PerfCounters {
+    /// Open a counter for every event the machine supports. Fails if none
+    /// can be opened, eg. in a container or VM without access to the PMU.
+    pub fn open() -> io::Result<Self> {
+        let mut counters = Vec::new();
+        let mut first_err = None;
+        for event in PerfEvent::ALL {
+            match open_counter(event) {
+                Ok(file) => counters.push((event, file)),
+                Err(e) => { first_err.get_or_insert(e); },
+            }
+        }
+        match first_err {
+            Some(e) if counters.is_empty() => Err(e),
+            _ => Ok(PerfCounters { counters }),
+        }
+    }
+
+    /// Read the counts so far, scaling up multiplexed counters.
+    pub fn read(&self) -> PerfCounts {
+        let mut counts = PerfCounts::default();
+        for (event, file) in &self.counters {
+            *counts.get_mut(*event) = read_counter(file);
+        }
+        counts
+    }
+}
+
+/// Read a counter opened with `PERF_FORMAT_TOTAL_TIME_ENABLED` and
+/// `PERF_FORMAT_TOTAL_TIME_RUNNING`.
+fn read_counter(mut file: &File) -> Option<u64> {
+    use std::io::Read;
+
+    let mut buf = [0u8; 24];
+    file.read_exact(&mut buf).ok()?;
+    let word = |i: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&buf[i * 8..i * 8 + 8]);
+        u64::from_ne_bytes(bytes)
+    };
+    let (value, enabled, running) = (word(0), word(1), word(2));
+    if running == 0 {
+        return None;
+    }
+    Some((value as f64 * enabled as f64 / running as f64).round() as u64)
+}
+
+/// Prefix of `struct perf_event_attr`, as of `PERF_ATTR_SIZE_VER5`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+#[cfg(target_os = "linux")]
+fn open_counter(event: PerfEvent) -> io::Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    const FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+    const FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+    const DISABLED: u64 = 1 << 0;
+    const INHERIT: u64 = 1 << 1;
+    const EXCLUDE_KERNEL: u64 = 1 << 5;
+    const EXCLUDE_HV: u64 = 1 << 6;
+    const ENABLE_ON_EXEC: u64 = 1 << 12;
+    const FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+    let (type_, config) = event.type_config();
+    let attr = PerfEventAttr {
+        type_,
+        size: std::mem::size_of::<PerfEventAttr>() as u32,
+        config,
+        read_format: FORMAT_TOTAL_TIME_ENABLED | FORMAT_TOTAL_TIME_RUNNING,
+        flags: DISABLED | INHERIT | EXCLUDE_KERNEL | EXCLUDE_HV | ENABLE_ON_EXEC,
+        ..PerfEventAttr::default()
+    };
+    // SAFETY: attr is a valid perf_event_attr of the size it declares, and
+    // the returned fd is owned by nothing else
+    let fd = unsafe {
+        libc::syscall(libc::SYS_perf_event_open, &attr as *const PerfEventAttr,
+            0 as libc::pid_t, -1 as libc::c_int, -1 as libc::c_int, FLAG_FD_CLOEXEC)
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as libc::c_int) })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_counter(_: PerfEvent) -> io::Result<File> {
+    Err(io::Error::new(io::ErrorKind::Other,
+        "hardware counters are only supported on Linux"))
+}
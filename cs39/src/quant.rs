@@ -1,20 +1,32 @@
 
 use crate::{
     cap_parse,
+    compile::Compiled,
+    perf::{PerfCounters, PerfCounts},
+    stats::{sample_rows, RunSpread, SampleRow, Summary},
+    units::{Millis, Secs},
+    error::{Error, Result},
 };
 use std::{
     process::{
+        Child,
         Command,
         Stdio,
         ExitStatus,
     },
-    sync::mpsc,
+    sync::{
+        mpsc,
+        atomic::{AtomicBool, Ordering},
+    },
     thread,
-    io::{Read, BufRead, BufReader},
+    io::{self, Read, BufRead, BufReader},
     borrow::BorrowMut,
+    fmt::{self, Display, Formatter},
     time::Duration,
 };
 use regex::Regex;
+use byte_unit::Byte;
+use serde::ser::SerializeSeq;
 
 pub fn parse_elapsed_time_line(line: &str) -> Option<Duration> {
     let pat = r##"^Running test iteration\s+\d+\s+\[Elapsed time : (?P<ms>\d+(?:\.\d+)?)ms\]$"##;
@@ -22,26 +34,153 @@ pub fn parse_elapsed_time_line(line: &str) -> Option<Duration> {
         
     pat
         .captures(line)
-        .map(|caps| cap_parse::<f64>(&caps, "ms").unwrap())
-        .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+        .and_then(|caps| cap_parse::<f64>(&caps, "ms"))
+        .and_then(|ms| Millis(ms).to_duration())
 }
 
-pub fn demo_min_time<I, L>(lines: I) -> Duration 
+/// Every iteration time reported by a demo, in order.
+pub fn demo_times<I, L>(lines: I) -> Vec<Duration>
 where
     I: IntoIterator<Item=L>,
     L: AsRef<str>,
 {
     lines.into_iter()
         .flat_map(|line| parse_elapsed_time_line(line.as_ref()))
-        .min()
-        .unwrap()
+        .collect()
+}
+
+/// Minimum iteration time reported by a demo, if any.
+pub fn demo_min_time<I, L>(lines: I) -> Option<Duration>
+where
+    I: IntoIterator<Item=L>,
+    L: AsRef<str>,
+{
+    demo_times(lines).into_iter().min()
+}
+
+/// Resources used by a finished sub-process, as reported by `wait4`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResourceUsage {
+    /// Peak resident set size.
+    pub max_rss_bytes: u64,
+    pub user_time: Duration,
+    pub sys_time: Duration,
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+    /// Page faults serviced without I/O.
+    pub minor_faults: u64,
+    /// Page faults which required I/O.
+    pub major_faults: u64,
+}
+
+impl ResourceUsage {
+    /// Names of the table columns written by `serialize_columns`.
+    pub const COLUMNS: &'static [&'static str] = &[
+        "MaxRssBytes",
+        "UserTimeMs",
+        "SysTimeMs",
+        "VoluntaryContextSwitches",
+        "InvoluntaryContextSwitches",
+        "MinorPageFaults",
+        "MajorPageFaults",
+    ];
+    
+    /// Serialize as consecutive columns of a table row.
+    pub fn serialize_columns<S: SerializeSeq>(&self, seq: &mut S) -> std::result::Result<(), S::Error> {
+        seq.serialize_element(&self.max_rss_bytes)?;
+        seq.serialize_element(&Millis::from(self.user_time))?;
+        seq.serialize_element(&Millis::from(self.sys_time))?;
+        seq.serialize_element(&self.voluntary_switches)?;
+        seq.serialize_element(&self.involuntary_switches)?;
+        seq.serialize_element(&self.minor_faults)?;
+        seq.serialize_element(&self.major_faults)?;
+        Ok(())
+    }
+    
+    /// Mean usage of several runs, except peak RSS, which is the maximum.
+    pub fn mean(runs: &[Self]) -> Self {
+        let n = runs.len().max(1) as u64;
+        let mean = |field: fn(&Self) -> u64| runs.iter().map(field).sum::<u64>() / n;
+        let mean_time = |field: fn(&Self) -> Duration| runs.iter()
+            .map(field)
+            .sum::<Duration>() / n as u32;
+        ResourceUsage {
+            max_rss_bytes: runs.iter().map(|run| run.max_rss_bytes).max().unwrap_or(0),
+            user_time: mean_time(|run| run.user_time),
+            sys_time: mean_time(|run| run.sys_time),
+            voluntary_switches: mean(|run| run.voluntary_switches),
+            involuntary_switches: mean(|run| run.involuntary_switches),
+            minor_faults: mean(|run| run.minor_faults),
+            major_faults: mean(|run| run.major_faults),
+        }
+    }
+    
+    #[cfg(unix)]
+    fn from_rusage(rusage: &libc::rusage) -> Self {
+        let time = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, 0)
+            + Duration::from_micros(tv.tv_usec as u64);
+        // linux reports kibibytes, macOS bytes
+        let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+        ResourceUsage {
+            max_rss_bytes: rusage.ru_maxrss as u64 * rss_unit,
+            user_time: time(rusage.ru_utime),
+            sys_time: time(rusage.ru_stime),
+            voluntary_switches: rusage.ru_nvcsw as u64,
+            involuntary_switches: rusage.ru_nivcsw as u64,
+            minor_faults: rusage.ru_minflt as u64,
+            major_faults: rusage.ru_majflt as u64,
+        }
+    }
+}
+
+impl Display for ResourceUsage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "peak RSS {}, cpu time {} user + {} sys, \
+            page faults {} minor + {} major, \
+            context switches {} voluntary + {} involuntary",
+            Byte::from_bytes(self.max_rss_bytes as u128)
+                .get_appropriate_unit(true)
+                .format(1),
+            Secs::from(self.user_time), Secs::from(self.sys_time),
+            self.minor_faults, self.major_faults,
+            self.voluntary_switches, self.involuntary_switches)
+    }
+}
+
+/// Wait for a child to exit, and collect its resource usage.
+#[cfg(unix)]
+fn wait_with_usage(child: &mut Child) -> io::Result<(ExitStatus, ResourceUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+    
+    let pid = child.id() as libc::pid_t;
+    let mut status: libc::c_int = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the pointers are to live locals, and the child hasn't been
+        // reaped, so the pid still refers to it
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) };
+        if ret == pid {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok((ExitStatus::from_raw(status), ResourceUsage::from_rusage(&rusage)))
+}
+
+/// Wait for a child to exit. Resource usage isn't available, so is zero.
+#[cfg(not(unix))]
+fn wait_with_usage(child: &mut Child) -> io::Result<(ExitStatus, ResourceUsage)> {
+    child.wait().map(|status| (status, ResourceUsage::default()))
 }
 
 /// Spawn a sub-process, and by the power of threads,
 /// elevate its stdout and stderr to the parent while
 /// also merging them together into a line stream,
-/// then collecting them.
-pub fn subproc<B>(mut command: B, quiet: bool) -> (ExitStatus, Vec<String>) 
+/// then collecting them, along with its resource usage.
+pub fn subproc<B>(mut command: B, quiet: bool) -> Result<(ExitStatus, Vec<String>, ResourceUsage)>
 where
     B: BorrowMut<Command>,
 {
@@ -53,7 +192,8 @@ where
     let (send_0, recv) = mpsc::sync_channel::<String>(10000);
     let send_1 = send_0.clone();
     
-    let mut child = cmd.spawn().unwrap();
+    let mut child = cmd.spawn()
+        .map_err(Error::spawn(cmd.get_program()))?;
     
     let stdout = child.stdout.take().unwrap();
     let stdout = Box::new(stdout) as Box<dyn Read + Send>;
@@ -64,14 +204,13 @@ where
     let mut threads = Vec::new();
     
     
-    for (read, send) in vec![
+    for (read, send) in [
         (stdout, send_0),
         (stderr, send_1),
     ] {
         let thread = thread::spawn(move || {
             let read = BufReader::new(read);
-            for line in read.lines() {
-                let line = line.unwrap();
+            for line in read.lines().map_while(|line| line.ok()) {
                 if !quiet { println!("{}", line); }
                 let _ = send.send(line);
             }
@@ -79,7 +218,8 @@ where
         threads.push(thread);
     }
     
-    let status = child.wait().unwrap();
+    let (status, usage) = wait_with_usage(&mut child)
+        .map_err(Error::spawn(cmd.get_program()))?;
     
     for thread in threads {
         thread.join().unwrap();
@@ -90,5 +230,211 @@ where
         lines.push(line);
     }
     
-    (status, lines)
-}
\ No newline at end of file
+    Ok((status, lines, usage))
+}
+
+/// Iterations at the start of each run left out of the statistics, unless
+/// specified otherwise.
+pub const DEFAULT_WARMUP: usize = 1;
+
+/// How to run a demo being benchmarked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BenchOptions {
+    /// `OMP_NUM_THREADS`, if set.
+    pub threads: Option<u32>,
+    /// Count hardware events in the demo.
+    pub perf: bool,
+    /// Iterations at the start of each run to leave out of the statistics,
+    /// as they may be slowed by cold caches and page faults.
+    pub warmup: usize,
+    /// Times to run each configuration, as separate processes.
+    pub repeat: usize,
+    /// Run each configuration once per round, rather than running all of a
+    /// configuration's repeats back to back, so slow drift in the machine's
+    /// performance affects every configuration alike.
+    pub interleave: bool,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            threads: None,
+            perf: false,
+            warmup: DEFAULT_WARMUP,
+            repeat: 1,
+            interleave: false,
+        }
+    }
+}
+
+/// Results of benchmarking a demo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    /// Minimum iteration time, excluding warm-up iterations.
+    pub min_time: Duration,
+    /// Every iteration time, including warm-up iterations, or if combined
+    /// from several runs, every iteration time after warm-up.
+    pub samples: Vec<Duration>,
+    pub summary: Summary,
+    /// Mean resource usage per run.
+    pub usage: ResourceUsage,
+    /// `Some` if hardware events were requested, though the counts are
+    /// empty if counters were unavailable. Mean per run.
+    pub counts: Option<PerfCounts>,
+    /// `Some` if combined from several runs.
+    pub spread: Option<RunSpread>,
+}
+
+impl Measurement {
+    /// Raw samples table rows, labelled with the configuration and which
+    /// run of it this was.
+    pub fn sample_rows(&self, config: &str, run: usize) -> Vec<SampleRow> {
+        sample_rows(config, run, &self.samples, &self.summary)
+    }
+    
+    /// Combine repeated runs of the same configuration, pooling their
+    /// samples after warm-up. A single run is returned as is.
+    ///
+    /// Panics if there are no runs.
+    pub fn combine(mut runs: Vec<Measurement>) -> Measurement {
+        if runs.len() == 1 {
+            return runs.pop().unwrap();
+        }
+        let samples: Vec<Duration> = runs.iter()
+            .flat_map(|run| run.samples[run.summary.warmup..].iter().copied())
+            .collect();
+        let summary = Summary::new(&samples, 0)
+            .expect("combined at least one run");
+        let usages: Vec<ResourceUsage> = runs.iter().map(|run| run.usage).collect();
+        let counts: Vec<PerfCounts> = runs.iter().filter_map(|run| run.counts).collect();
+        let summaries: Vec<Summary> = runs.into_iter().map(|run| run.summary).collect();
+        Measurement {
+            min_time: summary.min,
+            samples,
+            usage: ResourceUsage::mean(&usages),
+            counts: (!counts.is_empty()).then(|| PerfCounts::mean(&counts)),
+            spread: RunSpread::new(&summaries),
+            summary,
+        }
+    }
+}
+
+/// Run a built demo, and measure its iteration times and resource usage,
+/// which are reported.
+///
+/// With `options.perf`, hardware events are counted too, or if the machine
+/// can't count them, a warning is printed the first time.
+pub fn benchmark(
+    compiled: &Compiled,
+    major: u32,
+    minor: u32,
+    options: &BenchOptions,
+) -> Result<Measurement> {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    
+    let mut cmd = Command::new(&compiled.binary);
+    cmd.current_dir(&compiled.workdir);
+    if let Some(threads) = options.threads {
+        cmd.env("OMP_NUM_THREADS", threads.to_string());
+    }
+    let counters = match options.perf {
+        true => PerfCounters::open()
+            .map_err(|e| if !WARNED.swap(true, Ordering::SeqCst) {
+                println!("[WARN] hardware counters unavailable, leaving them \
+                    blank: {}", e);
+            })
+            .ok(),
+        false => None,
+    };
+    let (status, lines, usage) = subproc(cmd, false)?;
+    let counts = counters.map(|counters| counters.read())
+        .or_else(|| options.perf.then(PerfCounts::default));
+    if !status.success() {
+        return Err(Error::DemoFailed {
+            demo: (major, minor),
+            status,
+        });
+    }
+    
+    let samples = demo_times(&lines);
+    let summary = Summary::new(&samples, options.warmup)
+        .ok_or(Error::MissingOutput {
+            demo: (major, minor),
+            expected: "any iteration times",
+        })?;
+    println!();
+    println!("[INFO] {}", summary);
+    println!("[INFO] {}", usage);
+    if let Some(counts) = counts {
+        println!("[INFO] {}", counts);
+    }
+    Ok(Measurement {
+        min_time: summary.min,
+        samples,
+        summary,
+        usage,
+        counts,
+        spread: None,
+    })
+}
+
+/// Benchmark `configs` configurations `options.repeat` times each.
+///
+/// `bench(config, run)` runs one, counting runs from 1, and once all of a
+/// configuration's runs are done, `done(config, measurement)` is given them
+/// combined. With `options.interleave`, configurations take turns, one run
+/// per round, so they're all done at the end. If a run fails, `done` is
+/// given the error straight away, and the configuration's remaining runs are
+/// skipped. Stops at the first error `done` returns.
+pub fn repeat_runs<B, D>(
+    configs: usize,
+    options: &BenchOptions,
+    mut bench: B,
+    mut done: D,
+) -> Result<()>
+where
+    B: FnMut(usize, usize) -> Result<Measurement>,
+    D: FnMut(usize, Result<Measurement>) -> Result<()>,
+{
+    let repeat = options.repeat.max(1);
+    if options.interleave {
+        let mut runs: Vec<Option<Vec<Measurement>>> = vec![Some(Vec::new()); configs];
+        for run in 1..=repeat {
+            for (config, config_runs) in runs.iter_mut().enumerate() {
+                let measurements = match config_runs {
+                    Some(measurements) => measurements,
+                    None => continue,
+                };
+                match bench(config, run) {
+                    Ok(measurement) => measurements.push(measurement),
+                    Err(e) => {
+                        *config_runs = None;
+                        done(config, Err(e))?;
+                    },
+                }
+            }
+        }
+        for (config, config_runs) in runs.into_iter().enumerate() {
+            if let Some(measurements) = config_runs {
+                done(config, Ok(Measurement::combine(measurements)))?;
+            }
+        }
+    } else {
+        for config in 0..configs {
+            let runs = (1..=repeat)
+                .map(|run| bench(config, run))
+                .collect::<Result<Vec<_>>>();
+            done(config, runs.map(Measurement::combine))?;
+        }
+    }
+    Ok(())
+}
+
+/// Suffix for progress messages saying which run this is, eg.
+/// ` (run 2 of 5)`, or nothing if there's only one.
+pub fn run_suffix(run: usize, options: &BenchOptions) -> String {
+    match options.repeat {
+        0 | 1 => String::new(),
+        repeat => format!(" (run {} of {})", run, repeat),
+    }
+}
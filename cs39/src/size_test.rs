@@ -1,230 +1,476 @@
 
 use crate::{
     cap_parse,
+    error::{Error, Result},
     navigate::{
         DemoLookup,
         find_demo,
     },
     compile::{
         cpp_files, 
-        modify_compile,
-        Compiled,
+        build_all,
+        BuildOptions,
+        Profile,
+    },
+    defines::{
+        find_defines,
+        find_define,
+        choose_mechanism,
+        override_job,
+        Define,
+        DefineValue,
+        Override,
+    },
+    footprint::{
+        find_footprint,
+        Footprint,
+        MANIFEST_FILE,
     },
     output::{
         INFO_INDENT,
         TableWriter,
     },
     quant::{
-        subproc,
-        demo_min_time,
+        benchmark,
+        repeat_runs,
+        run_suffix,
+        BenchOptions,
+        Measurement,
+        ResourceUsage,
     },
+    perf::PerfCounts,
+    stats::{RunSpread, SampleRow, Summary},
+    units::Millis,
+    hardware::CpuInfo,
 };
 use std::{
-    path::Path,
-    process::Command,
-    mem::replace,
-    collections::HashMap,
+    collections::BTreeMap,
     fs::read_to_string,
     ffi::OsString,
 };
-use regex::{self, Regex};
+use regex::Regex;
 use byte_unit::Byte;
-use serde::Serialize;
-
-/// X or Y.
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
-#[repr(usize)]
-pub enum Dim { X, Y }
-
-/// Parse a `DIMX`/`DIMY` preprocessor directive.
-pub fn parse_dim_line(dim: Dim, line: &str) -> Option<u128> {
-    let pat = format!(
-        r#"^{}[[:space:]]+(?P<n>\d+)[[:space:]]*$"#,
-        regex::escape(&format!(
-            r##"#define {}DIM"##,
-            match dim {
-                Dim::X => 'X',
-                Dim::Y => 'Y',
-            },
-        )),
-    );
-    let pat = Regex::new(&pat).unwrap();
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+
+/// Bytes per grid element, as the demos store single-precision floats.
+///
+/// Only used to estimate the data size of demos without a footprint model.
+pub const ELEMENT_BYTES: u128 = 4;
+
+/// Largest data size tested, in bytes.
+pub const MAX_DATA_BYTES: u128 = 4 << 30;
+
+/// Grid dimension, defined as `#define XDIM 256`, `#define ZDIM 128`, etc.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Dim {
+    /// Macro name, eg. `XDIM`.
+    pub name: String,
+    pub size: u128,
+}
+
+/// Parse a `XDIM`/`YDIM`/`ZDIM`/etc. preprocessor directive.
+pub fn parse_dim_line(line: &str) -> Option<Dim> {
+    let pat = r##"^#define (?P<name>[A-Z]DIM)[[:space:]]+(?P<n>\d+)[[:space:]]*$"##;
+    let pat = Regex::new(pat).unwrap();
     
     pat.captures(line)
-        .map(|caps| cap_parse::<u128>(&caps, "n").unwrap())
+        .map(|caps| Dim {
+            name: caps["name"].to_owned(),
+            size: cap_parse::<u128>(&caps, "n").unwrap(),
+        })
 }
 
-/// Construct a `DIMX`/`DIMY` preprocessor directive.
-pub fn format_dim_line(dim: Dim, val: u128) -> String {
-    format!(
-        r##"#define {}DIM {}"##,
-        match dim {
-            Dim::X => 'X',
-            Dim::Y => 'Y',
-        },
-        val,
-    )
+/// Construct a `XDIM`/`YDIM`/`ZDIM`/etc. preprocessor directive.
+pub fn format_dim_line(dim: &Dim) -> String {
+    format!(r##"#define {} {}"##, dim.name, dim.size)
 }
 
-/// Search for DIMX/DIMY values in a demo.
+/// Search for the dimension definitions in a demo, sorted by name.
 pub fn find_dims(
     lookup: &DemoLookup, 
     major: u32,
     minor: u32
-) -> Result<(u128, u128), ()> {
+) -> Result<Vec<Dim>> {
     let path = find_demo(lookup, major, minor)?;
-    let mut found: [Option<u128>; 2] = [None, None];
-    for file in cpp_files(&path) {
-        let code = read_to_string(path.join(file)).unwrap();
-        for line in code.lines() {
-            for &dim in &[Dim::X, Dim::Y] {
-                if let Some(val) = parse_dim_line(dim, line) {
-                    if found[dim as usize].is_some() {
-                        println!("[ERROR] dimension {:?} defined twice in code", dim);
-                        return Err(());
-                    } else {
-                        found[dim as usize] = Some(val);
-                    }
+    let mut found: BTreeMap<String, (Dim, OsString, usize)> = BTreeMap::new();
+    for file in cpp_files(&path)? {
+        let file_path = path.join(&file);
+        let code = read_to_string(&file_path)
+            .map_err(Error::io(file_path))?;
+        for (i, line) in code.lines().enumerate() {
+            if let Some(dim) = parse_dim_line(line) {
+                if let Some((_, first_file, first_line)) = found.get(&dim.name) {
+                    return Err(Error::DuplicateDefine {
+                        demo: (major, minor),
+                        name: dim.name,
+                        file,
+                        line: i + 1,
+                        first_file: first_file.clone(),
+                        first_line: *first_line,
+                    });
                 }
+                found.insert(dim.name.clone(), (dim, file.clone(), i + 1));
             }
         }
     }
-    for &dim in &[Dim::X, Dim::Y] {
-        if found[dim as usize].is_none() {
-            println!("[ERROR] dimension {:?} not found in code", dim);
-            return Err(());
+    if found.is_empty() {
+        return Err(Error::MissingDims {
+            demo: (major, minor),
+        });
+    }
+    Ok(found.into_values().map(|(dim, _, _)| dim).collect())
+}
+
+/// Number of grid elements.
+pub fn product(dims: &[Dim]) -> u128 {
+    dims.iter().map(|dim| dim.size).product()
+}
+
+/// Scale every dimension by the same factor, so that the grid has about
+/// `target` elements, with the same aspect ratio.
+pub fn scale_dims(base: &[Dim], target: u128) -> Vec<Dim> {
+    let factor = (target as f64 / product(base) as f64)
+        .powf(1.0 / base.len() as f64);
+    base.iter()
+        .map(|dim| Dim {
+            name: dim.name.clone(),
+            size: ((dim.size as f64 * factor).round() as u128).max(1),
+        })
+        .collect()
+}
+
+/// Sequence of grid dimensions to test, doubling in element count, up to
+/// `MAX_DATA_BYTES` of data as measured by `data_size`.
+pub fn size_sequence<F>(base: &[Dim], data_size: F) -> Result<Vec<Vec<Dim>>>
+where
+    F: Fn(&[Dim]) -> Result<u128>,
+{
+    let base_product = product(base);
+    let mut curr = base_product;
+    
+    let incr = 1;
+    
+    for _ in 0..10 {
+        if (curr >> incr) >= (1 << 12) {
+            curr >>= incr;
+        } else {
+            break;
+        }
+    }
+    
+    let mut seq: Vec<Vec<Dim>> = Vec::new();
+    loop {
+        let dims = scale_dims(base, curr);
+        if seq.last() != Some(&dims) {
+            seq.push(dims);
+        }
+        if (curr << incr) > (base_product << 12)
+            || data_size(&scale_dims(base, curr << incr))? > MAX_DATA_BYTES {
+            break;
+        } else {
+            curr <<= incr;
         }
     }
-    Ok((found[0].unwrap(), found[1].unwrap()))
+    Ok(seq)
+}
+
+/// Bytes of memory the demo uses with a grid of these dimensions.
+///
+/// Evaluates the demo's footprint model, with the dimensions substituted into
+/// its numeric defines, or without a model, assumes one float per grid
+/// element.
+pub fn data_size_bytes(
+    footprint: Option<&Footprint>,
+    defines: &[Define],
+    dims: &[Dim],
+) -> Result<u128> {
+    let footprint = match footprint {
+        Some(footprint) => footprint,
+        None => return Ok(product(dims) * ELEMENT_BYTES),
+    };
+    let mut vars: BTreeMap<String, i128> = defines.iter()
+        .filter_map(|define| define.value.as_ref()
+            .and_then(|value| value.trim().parse().ok())
+            .map(|value| (define.name.clone(), value)))
+        .collect();
+    for dim in dims {
+        vars.insert(dim.name.clone(), dim.size as i128);
+    }
+    footprint.bytes(&vars)
+}
+
+/// Human-readable description of a grid, eg. `256×256×256 = 64 MiB`, given
+/// its data size.
+pub fn dims_pretty(dims: &[Dim], data_size_bytes: u128) -> String {
+    let sizes: Vec<String> = dims.iter()
+        .map(|dim| dim.size.to_string())
+        .collect();
+    let data_size_str = Byte::from_bytes(data_size_bytes)
+        .get_appropriate_unit(true)
+        .format(0);
+    format!("{} = {}", sizes.join("×"), data_size_str)
+}
+
+/// Header row of a size test table, with a column per dimension, and
+/// hardware event columns if `perf`.
+pub fn header(dims: &[Dim], perf: bool) -> Vec<String> {
+    dims.iter()
+        .map(|dim| format!("{}Size", dim.name.trim_end_matches("DIM")))
+        .chain([
+            "ProductSize",
+            "DataSizeBytes",
+            "DataSizeString",
+            "FitsIn",
+            "BestTimeMs",
+        ].iter()
+            .chain(Summary::COLUMNS)
+            .chain(RunSpread::COLUMNS)
+            .chain(ResourceUsage::COLUMNS)
+            .chain(if perf { PerfCounts::COLUMNS } else { &[] })
+            .map(|&column| column.to_owned()))
+        .collect()
 }
 
-#[derive(Clone, Debug, Serialize, PartialEq)]
-#[serde(rename_all = "PascalCase")]
+/// Row of a size test table.
+#[derive(Clone, Debug, PartialEq)]
 pub struct SizeTestRow {
-    x_size: u128,
-    y_size: u128,
-    product_size: u128,
-    data_size_bytes: u128,
-    data_size_string: String,
-    best_time_ms: f64,
+    pub dims: Vec<Dim>,
+    pub product_size: u128,
+    pub data_size_bytes: u128,
+    pub data_size_string: String,
+    /// Smallest cache level the data fits in, eg. `L2` or `RAM`, if the
+    /// caches are known.
+    pub fits_in: Option<String>,
+    pub best_time_ms: Millis,
+    pub summary: Summary,
+    /// `Some` if combined from several runs.
+    pub spread: Option<RunSpread>,
+    pub usage: ResourceUsage,
+    pub counts: Option<PerfCounts>,
+}
+
+impl Serialize for SizeTestRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for dim in &self.dims {
+            seq.serialize_element(&dim.size)?;
+        }
+        seq.serialize_element(&self.product_size)?;
+        seq.serialize_element(&self.data_size_bytes)?;
+        seq.serialize_element(&self.data_size_string)?;
+        seq.serialize_element(&self.fits_in)?;
+        seq.serialize_element(&self.best_time_ms)?;
+        self.summary.serialize_columns(&mut seq)?;
+        RunSpread::serialize_columns(self.spread.as_ref(), &mut seq)?;
+        self.usage.serialize_columns(&mut seq)?;
+        if let Some(counts) = &self.counts {
+            counts.serialize_columns(&mut seq)?;
+        }
+        seq.end()
+    }
 }
 
 /// `size_test` task.
-pub fn run<P>(
-    repo: P, 
+///
+/// Data sizes come from the demo's footprint model, declared in a
+/// `cs39-demo.toml` manifest. With `options.inject_defines`, guarded 
+/// dimensions are overridden with `-D` flags rather than by rewriting the
+/// source. Each size is run `bench.repeat` times, and every iteration time
+/// is written to `samples`, labelled by dimensions.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
     lookup: &DemoLookup, 
+    profile: &Profile,
     major: u32, 
     minor: u32,
+    options: &BuildOptions,
+    bench: &BenchOptions,
     mut table: TableWriter<SizeTestRow>,
-) -> Result<(), ()> 
-where
-    P: AsRef<Path> 
-{
+    mut samples: TableWriter<SampleRow>,
+) -> Result<()> {
     // find the default dimensions
-    let (base_x, base_y) = find_dims(lookup, major, minor)?;
-    println!("[INFO] default dimensions are {:?}", (base_x, base_y));
+    let base = find_dims(lookup, major, minor)?;
+    let defines = find_defines(lookup, major, minor)?;
+    let footprint = find_footprint(lookup, major, minor)?;
+    match &footprint {
+        Some(footprint) => println!("[INFO] footprint model is {}", footprint),
+        None => println!("[WARN] no footprint model in {}, assuming {} bytes \
+            per grid element", MANIFEST_FILE, ELEMENT_BYTES),
+    }
+    println!("[INFO] default dimensions are {}", dims_pretty(&base,
+        data_size_bytes(footprint.as_ref(), &defines, &base)?));
     
-    let dim_seq: Vec<(u128, u128)> = {
-        let mut vec = Vec::new();
-        
-        let base = base_x * base_y;
-        let mut curr = base;
-        
-        let incr = 1;
-        
-        for _ in 0..10 {
-            if (curr >> incr) >= (1 << 12) {
-                curr >>= incr;
-            } else {
-                break;
-            }
-        }
-        
-        loop {
-            vec.push(curr);
-            if (curr << incr) > (base << 12) {
-                break;
-            } else if (curr << incr) > ((1 << 30) * 4 / 4) {
-                break;
-            } else {
-                curr <<= incr;
-            }
-        }
-        
-        fn u128sqrt(n: u128) -> u128 {
-            (n as f64).sqrt() as u128
-        }
-        
-        vec
-            .into_iter()
-            .map(|s| {
-                let y = u128sqrt(base_y * s / base_x);
-                let mut x = s / y;
-                x += s % (x * y);
-                (x, y)
-            })
-            .collect()
-    };
+    let dim_seq = size_sequence(&base, 
+        |dims| data_size_bytes(footprint.as_ref(), &defines, dims))?;
+    let data_sizes = dim_seq.iter()
+        .map(|dims| data_size_bytes(footprint.as_ref(), &defines, dims))
+        .collect::<Result<Vec<_>>>()?;
+    
+    // annotate which cache each working set fits in
+    let cpu = CpuInfo::detect();
+    let fits_in: Vec<Option<String>> = data_sizes.iter()
+        .map(|&data_size| cpu.fits_in_name(data_size))
+        .collect();
+    let fits_in_pretty = |i: usize| fits_in[i].as_ref()
+        .map(|name| format!(" (fits in {})", name))
+        .unwrap_or_default();
     
     println!("[INFO] testing with dimensions:");
     let mut dim_pretty = Vec::new();
-    for &(x, y) in &dim_seq {
-        let data_size = x * y * 4;
-        let data_size_str = Byte::from_bytes(data_size)
-            .get_appropriate_unit(true)
-            .format(0);
-        let dim_pretty_curr = format!("{}×{} = {}", x, y, data_size_str);
-        println!("{} • {}", INFO_INDENT, dim_pretty_curr);
+    for (i, (dims, &data_size)) in dim_seq.iter().zip(&data_sizes).enumerate() {
+        let dim_pretty_curr = dims_pretty(dims, data_size);
+        println!("{} • {}{}", INFO_INDENT, dim_pretty_curr, fits_in_pretty(i));
         dim_pretty.push(dim_pretty_curr);
     }
+    if cpu.caches.is_empty() {
+        println!("[WARN] cpu caches could not be detected");
+    }
     
-    for (i, &(x, y)) in dim_seq.iter().enumerate() {
-        println!("[INFO] benchmarking dimension {}", &dim_pretty[i]);
-        
-        let Compiled { workdir, binary } = modify_compile(
-            &repo, lookup, major, minor, 
-            |code: &mut HashMap<OsString, String>| {
-                for (file, content) in replace(code, HashMap::new()) {
-                    let rewritten: String = content.lines()
-                        .map(|line: &str| {
-                            let mut line = line.to_owned();
-                            if parse_dim_line(Dim::X, &line).is_some() {
-                                line = format_dim_line(Dim::X, x);
-                            } else if parse_dim_line(Dim::Y, &line).is_some() {
-                                line = format_dim_line(Dim::Y, y);
-                            }
-                            line.push('\n');
-                            line
-                        })
-                        .collect();
-                    code.insert(file, rewritten);
-                }
-            })?;
-            
-        let (status, lines) = subproc(
-            Command::new(&binary)
-                .current_dir(&workdir), false);
-                
-        let min_time = demo_min_time(&lines);
-        println!("[INFO] best time = {:.2}ms", min_time.as_secs_f64() / 1000.0);   
+    println!();
+    
+    // build every variant up front, so the compiler can use every core
+    let mut mechanisms = Vec::new();
+    for dim in &base {
+        let define = find_define(&defines, (major, minor), &dim.name)?;
+        let mechanism = choose_mechanism(define, &[], options.inject_defines);
+        println!("[INFO] overriding {} by {}", dim.name, mechanism);
+        mechanisms.push(mechanism);
+    }
+    println!();
+    
+    let builds = dim_seq.iter()
+        .map(|dims| {
+            let overrides: Vec<Override> = dims.iter()
+                .zip(&mechanisms)
+                .map(|(dim, &mechanism)| Override {
+                    name: dim.name.clone(),
+                    value: DefineValue::Value(dim.size.to_string()),
+                    mechanism,
+                })
+                .collect();
+            override_job(lookup, profile, major, minor, &overrides)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let builds = build_all(&builds, options)
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    println!();
+    
+    repeat_runs(dim_seq.len(), bench, |i, run| {
+        println!("[INFO] benchmarking dimension {}{}{}", &dim_pretty[i], 
+            fits_in_pretty(i), run_suffix(run, bench));
+        let measurement = benchmark(&builds[i], major, minor, bench)?;
+        let label = dim_seq[i].iter()
+            .map(|dim| dim.size.to_string())
+            .collect::<Vec<_>>()
+            .join("×");
+        for row in measurement.sample_rows(&label, run) {
+            samples.write(row)?;
+        }
+        if bench.repeat > 1 {
+            println!();
+        }
+        Ok(measurement)
+    }, |i, measurement| {
+        let Measurement { min_time, summary, usage, counts, spread, .. } = measurement?;
+        if let Some(spread) = &spread {
+            println!("[INFO] dimension {}, {}", &dim_pretty[i], spread);
+        }
+        println!("[INFO] best time = {}", Millis::from(min_time));
 
         table.write(SizeTestRow {
-            x_size: x,
-            y_size: y,
-            product_size: x * y,
-            data_size_bytes: x * y * 4,
+            dims: dim_seq[i].clone(),
+            product_size: product(&dim_seq[i]),
+            data_size_bytes: data_sizes[i],
             data_size_string: dim_pretty[i].clone(),
-            best_time_ms: min_time.as_secs_f64() / 1000.0
-        });
-            
+            fits_in: fits_in[i].clone(),
+            best_time_ms: Millis::from(min_time),
+            summary,
+            spread,
+            usage,
+            counts,
+        })?;
         println!();
-        if !status.success() {
-            println!("[ERROR] exit code {}", status.code().unwrap());
-            return Err(());
-        }
-    }
+        Ok(())
+    })?;
     
     println!("[INFO] done");
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dims(sizes: &[(&str, u128)]) -> Vec<Dim> {
+        sizes.iter()
+            .map(|&(name, size)| Dim { name: name.to_owned(), size })
+            .collect()
+    }
+
+    fn element_bytes(dims: &[Dim]) -> Result<u128> {
+        Ok(product(dims) * ELEMENT_BYTES)
+    }
+
+    #[test]
+    fn scale_dims_keeps_aspect_ratio() {
+        let base = dims(&[("XDIM", 256), ("YDIM", 128), ("ZDIM", 64)]);
+        assert_eq!(scale_dims(&base, product(&base) * 8),
+            dims(&[("XDIM", 512), ("YDIM", 256), ("ZDIM", 128)]));
+        assert_eq!(scale_dims(&base, product(&base) / 8),
+            dims(&[("XDIM", 128), ("YDIM", 64), ("ZDIM", 32)]));
+        assert_eq!(scale_dims(&base, product(&base)), base);
+    }
+
+    #[test]
+    fn scale_dims_rounds() {
+        // scaled by √2 to 141.4 and 42.4
+        let base = dims(&[("XDIM", 100), ("YDIM", 30)]);
+        assert_eq!(scale_dims(&base, 6000), dims(&[("XDIM", 141), ("YDIM", 42)]));
+        // scaled by √0.5 to 70.7 and 21.2
+        assert_eq!(scale_dims(&base, 1500), dims(&[("XDIM", 71), ("YDIM", 21)]));
+        // never rounded down to 0
+        let base = dims(&[("XDIM", 1000), ("YDIM", 2)]);
+        assert_eq!(scale_dims(&base, 1), dims(&[("XDIM", 22), ("YDIM", 1)]));
+    }
+
+    #[test]
+    fn size_sequence_increases() {
+        let base = dims(&[("XDIM", 256), ("YDIM", 128), ("ZDIM", 100)]);
+        let seq = size_sequence(&base, element_bytes).unwrap();
+        assert!(seq.len() > 10);
+        assert!(product(&seq[0]) < product(&base));
+        assert!(seq.contains(&base));
+        for pair in seq.windows(2) {
+            assert!(product(&pair[1]) > product(&pair[0]));
+            for (a, b) in pair[0].iter().zip(&pair[1]) {
+                assert_eq!(a.name, b.name);
+                assert!(b.size >= a.size);
+            }
+        }
+    }
+
+    #[test]
+    fn size_sequence_stops_at_cap() {
+        let base = dims(&[("XDIM", 256), ("YDIM", 256), ("ZDIM", 256)]);
+        let seq = size_sequence(&base, element_bytes).unwrap();
+        let last = seq.last().unwrap();
+        assert!(element_bytes(last).unwrap() <= MAX_DATA_BYTES);
+        assert!(element_bytes(last).unwrap() * 2 > MAX_DATA_BYTES);
+
+        // a larger footprint per element stops it sooner
+        let heavy = |dims: &[Dim]| Ok(product(dims) * 64);
+        let heavy_seq = size_sequence(&base, heavy).unwrap();
+        assert!(heavy_seq.len() < seq.len());
+        assert!(heavy(heavy_seq.last().unwrap()).unwrap() <= MAX_DATA_BYTES);
+        assert_eq!(seq[..heavy_seq.len()], heavy_seq[..]);
+    }
+
+    #[test]
+    fn size_sequence_propagates_errors() {
+        let base = dims(&[("XDIM", 256)]);
+        let failing = |_: &[Dim]| Err(Error::MissingDims { demo: (0, 0) });
+        assert!(size_sequence(&base, failing).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use serde::{Deserialize, Serialize};
+use crate::units::Millis;
+
+/// How far outside the interquartile range a sample must be to count as an
+/// outlier, in multiples of the interquartile range (Tukey's fences).
+pub const OUTLIER_FENCE: f64 = 1.5;
+
+/// Summary statistics of a demo's iteration times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// Number of samples the statistics are over, after warm-up.
+    pub samples: usize,
+    /// Number of warm-up samples excluded.
+    pub warmup: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub mean: Duration,
+    /// Sample standard deviation.
+    pub stddev: Duration,
+    /// 10th percentile.
+    pub p10: Duration,
+    /// 90th percentile.
+    pub p90: Duration,
+    /// Coefficient of variation, `stddev / mean`.
+    pub cv: f64,
+    /// Indices of the outlying samples, counting warm-up samples.
+    pub outliers: Vec<usize>,
+}
+
+impl Summary {
+    /// Names of the table columns written by `serialize_columns`.
+    pub const COLUMNS: &'static [&'static str] = &[
+        "Samples",
+        "MedianMs",
+        "MeanMs",
+        "StddevMs",
+        "P10Ms",
+        "P90Ms",
+        "Cv",
+        "Outliers",
+    ];
+
+    /// Summarize samples, excluding the first `warmup`, though never all of
+    /// them. `None` if there are no samples.
+    pub fn new(samples: &[Duration], warmup: usize) -> Option<Self> {
+        let warmup = warmup.min(samples.len().checked_sub(1)?);
+        let secs: Vec<f64> = samples[warmup..].iter()
+            .map(Duration::as_secs_f64)
+            .collect();
+        let mut sorted = secs.clone();
+        sorted.sort_by(f64::total_cmp);
+
+        let n = secs.len() as f64;
+        let mean = secs.iter().sum::<f64>() / n;
+        let variance = match secs.len() {
+            1 => 0.0,
+            _ => secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0),
+        };
+        let stddev = variance.sqrt();
+
+        let (q1, q3) = (percentile(&sorted, 25.0), percentile(&sorted, 75.0));
+        let fence = OUTLIER_FENCE * (q3 - q1);
+        let outliers = secs.iter()
+            .enumerate()
+            .filter(|&(_, &s)| s < q1 - fence || s > q3 + fence)
+            .map(|(i, _)| i + warmup)
+            .collect();
+
+        Some(Summary {
+            samples: secs.len(),
+            warmup,
+            min: Duration::from_secs_f64(sorted[0]),
+            median: Duration::from_secs_f64(percentile(&sorted, 50.0)),
+            mean: Duration::from_secs_f64(mean),
+            stddev: Duration::from_secs_f64(stddev),
+            p10: Duration::from_secs_f64(percentile(&sorted, 10.0)),
+            p90: Duration::from_secs_f64(percentile(&sorted, 90.0)),
+            cv: if mean > 0.0 { stddev / mean } else { 0.0 },
+            outliers,
+        })
+    }
+
+    /// Serialize as consecutive columns of a table row.
+    pub fn serialize_columns<S: serde::ser::SerializeSeq>(
+        &self,
+        seq: &mut S,
+    ) -> Result<(), S::Error> {
+        seq.serialize_element(&self.samples)?;
+        seq.serialize_element(&Millis::from(self.median))?;
+        seq.serialize_element(&Millis::from(self.mean))?;
+        seq.serialize_element(&Millis::from(self.stddev))?;
+        seq.serialize_element(&Millis::from(self.p10))?;
+        seq.serialize_element(&Millis::from(self.p90))?;
+        seq.serialize_element(&self.cv)?;
+        seq.serialize_element(&self.outliers.len())?;
+        Ok(())
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "median {}, mean {} ± {} (cv {:.1}%), p10 {}, p90 {}, \
+            over {} samples",
+            Millis::from(self.median), Millis::from(self.mean),
+            Millis::from(self.stddev), self.cv * 100.0,
+            Millis::from(self.p10), Millis::from(self.p90), self.samples)?;
+        if self.warmup > 0 {
+            write!(f, " after {} warm-up", self.warmup)?;
+        }
+        match self.outliers.len() {
+            0 => Ok(()),
+            1 => write!(f, ", 1 outlier"),
+            n => write!(f, ", {} outliers", n),
+        }
+    }
+}
+
+/// Percentile of sorted values, interpolating linearly between them.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.len() == 1 {
+        return sorted[0];
+    }
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+/// Two-sided p-value of the Mann-Whitney U test, of whether samples `a`
+/// and `b` come from the same distribution, against one tending to be larger
+/// than the other.
+///
+/// Uses the normal approximation, with corrections for ties and continuity,
+/// so is only reliable with several samples of each. `None` if either is
+/// empty.
+pub fn mann_whitney_p(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let mut all: Vec<(f64, bool)> = a.iter().map(|&x| (x, true))
+        .chain(b.iter().map(|&x| (x, false)))
+        .collect();
+    all.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // rank, averaging the ranks of ties
+    let n = all.len() as f64;
+    let (mut rank_sum_a, mut ties) = (0.0, 0.0);
+    let mut i = 0;
+    while i < all.len() {
+        let j = i + all[i..].iter().take_while(|x| x.0 == all[i].0).count();
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum_a += rank * all[i..j].iter().filter(|x| x.1).count() as f64;
+        let t = (j - i) as f64;
+        ties += t * t * t - t;
+        i = j;
+    }
+
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let u = rank_sum_a - n_a * (n_a + 1.0) / 2.0;
+    let mean = n_a * n_b / 2.0;
+    let variance = n_a * n_b / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return Some(1.0);
+    }
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some(erfc(z / std::f64::consts::SQRT_2).min(1.0))
+}
+
+/// Complementary error function, to within 1.2e-7, after Numerical Recipes.
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = -x * x - 1.26551223 + t * (1.00002368 + t * (0.37409196
+        + t * (0.09678418 + t * (-0.18628806 + t * (0.27886807
+        + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223
+        + t * 0.17087277))))))));
+    let ans = t * poly.exp();
+    if x >= 0.0 { ans } else { 2.0 - ans }
+}
+
+/// Row of a raw samples table, with one row per iteration of each run.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SampleRow {
+    /// Which configuration was run, eg. the thread count or dimensions.
+    pub config: String,
+    /// Which run of the configuration, counting from 1.
+    pub run: usize,
+    /// Counting from 1, as the demos do.
+    pub iteration: usize,
+    pub time_ms: Millis,
+    /// Excluded from statistics as a warm-up iteration.
+    pub warmup: bool,
+    pub outlier: bool,
+}
+
+/// Raw samples table rows for one run.
+pub fn sample_rows(
+    config: &str,
+    run: usize,
+    samples: &[Duration],
+    summary: &Summary,
+) -> Vec<SampleRow> {
+    samples.iter()
+        .enumerate()
+        .map(|(i, time)| SampleRow {
+            config: config.to_owned(),
+            run,
+            iteration: i + 1,
+            time_ms: Millis::from(*time),
+            warmup: i < summary.warmup,
+            outlier: summary.outliers.contains(&i),
+        })
+        .collect()
+}
+
+/// Spread of iteration times across repeated runs of a demo, separating
+/// run-to-run variation from variation between iterations within a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSpread {
+    pub runs: usize,
+    /// Sample standard deviation of the runs' medians.
+    pub between_stddev: Duration,
+    /// `between_stddev` over the mean of the runs' medians.
+    pub between_cv: f64,
+    /// Pooled standard deviation of iterations within each run.
+    pub within_stddev: Duration,
+    /// `within_stddev` over the mean of every sample.
+    pub within_cv: f64,
+}
+
+impl RunSpread {
+    /// Names of the table columns written by `serialize_columns`.
+    pub const COLUMNS: &'static [&'static str] = &[
+        "Runs",
+        "RunToRunStddevMs",
+        "RunToRunCv",
+        "WithinRunStddevMs",
+        "WithinRunCv",
+    ];
+
+    /// Spread across the summaries of each run, or `None` if there are fewer
+    /// than two.
+    pub fn new(runs: &[Summary]) -> Option<Self> {
+        if runs.len() < 2 {
+            return None;
+        }
+        let secs = |time: Duration| time.as_secs_f64();
+        let n = runs.len() as f64;
+        let medians_mean = runs.iter().map(|run| secs(run.median)).sum::<f64>() / n;
+        let between = (runs.iter()
+            .map(|run| (secs(run.median) - medians_mean).powi(2))
+            .sum::<f64>() / (n - 1.0)).sqrt();
+
+        let samples = runs.iter().map(|run| run.samples).sum::<usize>() as f64;
+        let mean = runs.iter()
+            .map(|run| secs(run.mean) * run.samples as f64)
+            .sum::<f64>() / samples;
+        let dof = runs.iter().map(|run| run.samples - 1).sum::<usize>();
+        let within = match dof {
+            0 => 0.0,
+            dof => (runs.iter()
+                .map(|run| secs(run.stddev).powi(2) * (run.samples - 1) as f64)
+                .sum::<f64>() / dof as f64).sqrt(),
+        };
+
+        let cv = |stddev: f64, mean: f64| if mean > 0.0 { stddev / mean } else { 0.0 };
+        Some(RunSpread {
+            runs: runs.len(),
+            between_stddev: Duration::from_secs_f64(between),
+            between_cv: cv(between, medians_mean),
+            within_stddev: Duration::from_secs_f64(within),
+            within_cv: cv(within, mean),
+        })
+    }
+
+    /// Serialize as consecutive columns of a table row, for a single run if
+    /// `None`.
+    pub fn serialize_columns<S: serde::ser::SerializeSeq>(
+        spread: Option<&Self>,
+        seq: &mut S,
+    ) -> Result<(), S::Error> {
+        seq.serialize_element(&spread.map_or(1, |spread| spread.runs))?;
+        seq.serialize_element(&spread.map(|spread| Millis::from(spread.between_stddev)))?;
+        seq.serialize_element(&spread.map(|spread| spread.between_cv))?;
+        seq.serialize_element(&spread.map(|spread| Millis::from(spread.within_stddev)))?;
+        seq.serialize_element(&spread.map(|spread| spread.within_cv))?;
+        Ok(())
+    }
+}
+
+impl Display for RunSpread {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "across {} runs, run-to-run stddev {} (cv {:.1}%), \
+            within-run stddev {} (cv {:.1}%)",
+            self.runs, Millis::from(self.between_stddev), self.between_cv * 100.0,
+            Millis::from(self.within_stddev), self.within_cv * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_interpolates() {
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 50.0), 2.5);
+        assert_eq!(percentile(&sorted, 100.0), 4.0);
+        assert_eq!(percentile(&sorted, 150.0), 4.0);
+        assert_eq!(percentile(&[7.0], 90.0), 7.0);
+    }
+
+    #[test]
+    fn erfc_matches_known_values() {
+        assert!((erfc(0.0) - 1.0).abs() < 1.2e-7);
+        assert!((erfc(1.0) - 0.157_299_207).abs() < 1.2e-7);
+        assert!((erfc(-1.0) - 1.842_700_793).abs() < 1.2e-7);
+        assert!((erfc(2.0) - 0.004_677_735).abs() < 1.2e-7);
+    }
+
+    #[test]
+    fn mann_whitney_separated_samples() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [6.0, 7.0, 8.0, 9.0, 10.0];
+        let p = mann_whitney_p(&a, &b).unwrap();
+        assert!((p - 0.0122).abs() < 1e-4, "p = {}", p);
+    }
+
+    #[test]
+    fn mann_whitney_all_tied() {
+        assert_eq!(mann_whitney_p(&[3.0; 5], &[3.0; 5]), Some(1.0));
+    }
+
+    #[test]
+    fn mann_whitney_is_symmetric() {
+        let a = [1.0, 2.0, 3.0, 5.0, 8.0, 8.0];
+        let b = [2.0, 4.0, 6.0, 7.0, 9.0];
+        let p_ab = mann_whitney_p(&a, &b).unwrap();
+        let p_ba = mann_whitney_p(&b, &a).unwrap();
+        assert!((p_ab - p_ba).abs() < 1e-12, "{} != {}", p_ab, p_ba);
+        assert!(p_ab > 0.0 && p_ab <= 1.0);
+    }
+
+    #[test]
+    fn mann_whitney_empty() {
+        assert_eq!(mann_whitney_p(&[], &[1.0]), None);
+        assert_eq!(mann_whitney_p(&[1.0], &[]), None);
+    }
+}
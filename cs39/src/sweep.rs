@@ -0,0 +1,345 @@
+use crate::{
+    error::{Error, Result},
+    navigate::DemoLookup,
+    compile::{
+        build_all,
+        BuildOptions,
+        Profile,
+    },
+    defines::{
+        find_defines,
+        find_define,
+        choose_mechanism,
+        override_job,
+        DefineValue,
+        Override,
+    },
+    output::{
+        INFO_INDENT,
+        TableWriter,
+    },
+    quant::{
+        benchmark,
+        BenchOptions,
+        Measurement,
+    },
+    perf::PerfCounts,
+    stats::{SampleRow, Summary},
+    units::Millis,
+};
+use regex::Regex;
+use serde::{Serialize, Serializer, ser::SerializeSeq};
+
+/// A define to sweep, and the values to sweep it through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub values: Vec<DefineValue>,
+}
+
+/// Parse `;`-separated parameter specs, eg. `XDIM=256..4096*2;CHANGES=true,false`.
+///
+/// Each spec is `NAME=VALUES`, where values are comma-separated, and each is
+/// either a literal, `defined`, `undef`, or an integer range. Ranges are
+/// inclusive, and are `A..B` (step 1), `A..B:STEP`, or `A..B*FACTOR`.
+/// `None` if malformed.
+pub fn parse_params(s: &str) -> Option<Vec<Param>> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            let (name, values) = spec.split_at(spec.find('=')?);
+            let name = name.trim();
+            Regex::new(r#"^[[:alpha:]_][[:word:]]*$"#).unwrap()
+                .is_match(name)
+                .then_some(())?;
+            let mut parsed = Vec::new();
+            for value in values[1..].split(',').map(str::trim) {
+                parsed.extend(parse_param_values(value)?);
+            }
+            Some(Param {
+                name: name.to_owned(),
+                values: parsed,
+            }).filter(|param| !param.values.is_empty())
+        })
+        .collect()
+}
+
+/// Parse one comma-separated item of a parameter spec.
+fn parse_param_values(s: &str) -> Option<Vec<DefineValue>> {
+    let pat = r#"^(?P<start>-?\d+)\.\.(?P<end>-?\d+)(?:(?P<op>[:*])(?P<step>\d+))?$"#;
+    let pat = Regex::new(pat).unwrap();
+
+    let caps = match pat.captures(s) {
+        Some(caps) => caps,
+        None => return match s {
+            "" => None,
+            "defined" => Some(vec![DefineValue::Defined]),
+            "undef" => Some(vec![DefineValue::Undefined]),
+            s => Some(vec![DefineValue::Value(s.to_owned())]),
+        },
+    };
+    let start: i64 = caps["start"].parse().ok()?;
+    let end: i64 = caps["end"].parse().ok()?;
+    let step: i64 = match caps.name("step") {
+        Some(step) => step.as_str().parse().ok()?,
+        None => 1,
+    };
+    let multiply = caps.name("op").map(|op| op.as_str()) == Some("*");
+    if end < start || (multiply && (step < 2 || start <= 0)) || step < 1 {
+        return None;
+    }
+
+    let mut values = Vec::new();
+    let mut curr = start;
+    while curr <= end {
+        values.push(DefineValue::Value(curr.to_string()));
+        curr = if multiply { curr.checked_mul(step)? } else { curr.checked_add(step)? };
+    }
+    Some(values)
+}
+
+/// Every combination of values of the parameters, in the same order.
+pub fn combinations(params: &[Param]) -> Vec<Vec<DefineValue>> {
+    params.iter()
+        .fold(vec![Vec::new()], |combos, param| combos.iter()
+            .flat_map(|combo| param.values.iter()
+                .map(move |value| {
+                    let mut combo = combo.clone();
+                    combo.push(value.clone());
+                    combo
+                }))
+            .collect())
+}
+
+/// Short human-readable description of a combination.
+pub fn label(params: &[Param], combo: &[DefineValue]) -> String {
+    params.iter()
+        .zip(combo)
+        .map(|(param, value)| format!("{}={}", param.name, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Header row of a sweep table, with hardware event columns if `perf`.
+pub fn header(params: &[Param], perf: bool) -> Vec<String> {
+    params.iter()
+        .map(|param| param.name.clone())
+        .chain(["BestTimeMs"].iter()
+            .chain(Summary::COLUMNS)
+            .chain(if perf { PerfCounts::COLUMNS } else { &[] })
+            .map(|&column| column.to_owned()))
+        .collect()
+}
+
+/// Row of a sweep table, with a column per parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SweepRow {
+    pub values: Vec<DefineValue>,
+    pub best_time_ms: Millis,
+    pub summary: Summary,
+    pub counts: Option<PerfCounts>,
+}
+
+impl Serialize for SweepRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for value in &self.values {
+            seq.serialize_element(&value.to_string())?;
+        }
+        seq.serialize_element(&self.best_time_ms)?;
+        self.summary.serialize_columns(&mut seq)?;
+        if let Some(counts) = &self.counts {
+            counts.serialize_columns(&mut seq)?;
+        }
+        seq.end()
+    }
+}
+
+/// `sweep` subtask, listing the defines which could be swept.
+pub fn list(lookup: &DemoLookup, major: u32, minor: u32) -> Result<()> {
+    println!("[INFO] defines in demo {:?}:", (major, minor));
+    for define in find_defines(lookup, major, minor)? {
+        println!("{} • {} = {} ({:?}:{}{})", INFO_INDENT, define.name,
+            define.value.as_deref().unwrap_or("defined"),
+            define.file, define.line,
+            if define.guarded { ", #ifndef guarded" } else { "" });
+    }
+    Ok(())
+}
+
+/// `sweep` task.
+///
+/// With `options.inject_defines`, guarded defines are overridden with `-D`
+/// flags rather than by rewriting the source. Every combination is built
+/// first, up to `options.jobs` at a time, then benchmarked one by one.
+/// Combinations which fail to build or run are reported and skipped, and the
+/// task fails at the end if any did. Every iteration time is written to
+/// `samples`, labelled by combination.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    lookup: &DemoLookup,
+    profile: &Profile,
+    major: u32,
+    minor: u32,
+    params: &[Param],
+    options: &BuildOptions,
+    bench: &BenchOptions,
+    mut table: TableWriter<SweepRow>,
+    mut samples: TableWriter<SampleRow>,
+) -> Result<()> {
+    // each swept define must occur exactly once
+    let defines = find_defines(lookup, major, minor)?;
+    let mut mechanisms = Vec::new();
+    for param in params {
+        let define = find_define(&defines, (major, minor), &param.name)?;
+        let mechanism = choose_mechanism(define, &param.values, options.inject_defines);
+        println!("[INFO] default {} = {}, overriding by {}", define.name,
+            define.value.as_deref().unwrap_or("defined"), mechanism);
+        mechanisms.push(mechanism);
+    }
+
+    let combos = combinations(params);
+    println!("[INFO] testing {} combinations:", combos.len());
+    for combo in &combos {
+        println!("{} • {}", INFO_INDENT, label(params, combo));
+    }
+    println!();
+
+    let builds = combos.iter()
+        .map(|combo| {
+            let overrides: Vec<Override> = params.iter()
+                .zip(combo)
+                .zip(&mechanisms)
+                .map(|((param, value), &mechanism)| Override {
+                    name: param.name.clone(),
+                    value: value.clone(),
+                    mechanism,
+                })
+                .collect();
+            override_job(lookup, profile, major, minor, &overrides)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let builds = build_all(&builds, options);
+    println!();
+
+    let mut failed = Vec::new();
+    for (combo, build) in combos.iter().zip(builds) {
+        println!("[INFO] benchmarking {}", label(params, combo));
+        match build.and_then(|compiled| benchmark(&compiled, major, minor, bench)) {
+            Ok(measurement) => {
+                for row in measurement.sample_rows(&label(params, combo), 1) {
+                    samples.write(row)?;
+                }
+                let Measurement { min_time, summary, counts, .. } = measurement;
+                println!("[INFO] best time = {}", Millis::from(min_time));
+                table.write(SweepRow {
+                    values: combo.clone(),
+                    best_time_ms: Millis::from(min_time),
+                    summary,
+                    counts,
+                })?;
+            },
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                failed.push(label(params, combo));
+            },
+        }
+        println!();
+    }
+
+    if failed.is_empty() {
+        println!("[INFO] done");
+        Ok(())
+    } else {
+        Err(Error::ConfigsFailed {
+            demo: (major, minor),
+            failed,
+            total: combos.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[&str]) -> Vec<DefineValue> {
+        values.iter().map(|value| DefineValue::Value((*value).to_owned())).collect()
+    }
+
+    #[test]
+    fn range_with_step() {
+        assert_eq!(parse_param_values("1..10:3"), Some(values(&["1", "4", "7", "10"])));
+        assert_eq!(parse_param_values("-2..2"), Some(values(&["-2", "-1", "0", "1", "2"])));
+    }
+
+    #[test]
+    fn geometric_range() {
+        assert_eq!(parse_param_values("8..64*2"), Some(values(&["8", "16", "32", "64"])));
+        assert_eq!(parse_param_values("1..100*10"), Some(values(&["1", "10", "100"])));
+    }
+
+    #[test]
+    fn single_values() {
+        assert_eq!(parse_param_values("4..4"), Some(values(&["4"])));
+        assert_eq!(parse_param_values("256"), Some(values(&["256"])));
+        assert_eq!(parse_param_values("true"), Some(values(&["true"])));
+        assert_eq!(parse_param_values("defined"), Some(vec![DefineValue::Defined]));
+        assert_eq!(parse_param_values("undef"), Some(vec![DefineValue::Undefined]));
+    }
+
+    #[test]
+    fn reversed_or_empty_range() {
+        assert_eq!(parse_param_values("5..4"), None);
+        assert_eq!(parse_param_values("64..8*2"), None);
+        assert_eq!(parse_param_values(""), None);
+    }
+
+    #[test]
+    fn steps_which_would_never_end() {
+        assert_eq!(parse_param_values("1..5:0"), None);
+        assert_eq!(parse_param_values("1..8*1"), None);
+        assert_eq!(parse_param_values("1..8*0"), None);
+        assert_eq!(parse_param_values("0..8*2"), None);
+        assert_eq!(parse_param_values("-4..8*2"), None);
+    }
+
+    #[test]
+    fn overflowing_range() {
+        assert_eq!(parse_param_values("1..9223372036854775807*2"), None);
+        assert_eq!(parse_param_values("1..99999999999999999999"), None);
+    }
+
+    #[test]
+    fn params() {
+        let params = parse_params(" BLOCK_SIZE=8..32*2 ; CHANGES=true,false,undef ;").unwrap();
+        assert_eq!(params, vec![
+            Param {
+                name: "BLOCK_SIZE".to_owned(),
+                values: values(&["8", "16", "32"]),
+            },
+            Param {
+                name: "CHANGES".to_owned(),
+                values: vec![
+                    DefineValue::Value("true".to_owned()),
+                    DefineValue::Value("false".to_owned()),
+                    DefineValue::Undefined,
+                ],
+            },
+        ]);
+        assert_eq!(combinations(&params).len(), 9);
+        assert_eq!(label(&params, &combinations(&params)[1]), "BLOCK_SIZE=8 CHANGES=false");
+    }
+
+    #[test]
+    fn malformed_params() {
+        assert_eq!(parse_params("XDIM"), None);
+        assert_eq!(parse_params("=1,2"), None);
+        assert_eq!(parse_params("1DIM=1,2"), None);
+        assert_eq!(parse_params("X DIM=1"), None);
+        assert_eq!(parse_params("XDIM="), None);
+        assert_eq!(parse_params("XDIM=1,,2"), None);
+        assert_eq!(parse_params("XDIM=5..1"), None);
+    }
+}
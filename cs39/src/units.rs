@@ -0,0 +1,90 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Nanoseconds per millisecond.
+const NANOS_PER_MILLI: f64 = 1_000_000.0;
+
+/// Nanoseconds per second.
+const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+
+/// Time in milliseconds, the unit the demos report in and the unit of every
+/// `...Ms` table column.
+///
+/// Displays with an `ms` suffix, to 2 decimal places unless a precision is
+/// given, and serializes as a bare number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Millis(pub f64);
+
+impl Millis {
+    /// Convert to a duration, rounded to the nanosecond. Negative or
+    /// non-finite times are `None`.
+    pub fn to_duration(self) -> Option<Duration> {
+        to_duration(self.0 * NANOS_PER_MILLI)
+    }
+}
+
+impl From<Duration> for Millis {
+    fn from(time: Duration) -> Self {
+        Millis(time.as_nanos() as f64 / NANOS_PER_MILLI)
+    }
+}
+
+impl Display for Millis {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:.*}ms", f.precision().unwrap_or(2), self.0)
+    }
+}
+
+impl Serialize for Millis {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Millis {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Millis)
+    }
+}
+
+/// Time in seconds, for console output of longer times.
+///
+/// Displays with an `s` suffix, to 2 decimal places unless a precision is
+/// given, and serializes as a bare number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Secs(pub f64);
+
+impl Secs {
+    /// Convert to a duration, rounded to the nanosecond. Negative or
+    /// non-finite times are `None`.
+    pub fn to_duration(self) -> Option<Duration> {
+        to_duration(self.0 * NANOS_PER_SEC)
+    }
+}
+
+impl From<Duration> for Secs {
+    fn from(time: Duration) -> Self {
+        Secs(time.as_nanos() as f64 / NANOS_PER_SEC)
+    }
+}
+
+impl Display for Secs {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:.*}s", f.precision().unwrap_or(2), self.0)
+    }
+}
+
+impl Serialize for Secs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.0)
+    }
+}
+
+fn to_duration(nanos: f64) -> Option<Duration> {
+    let nanos = nanos.round();
+    (nanos.is_finite() && nanos >= 0.0 && nanos <= u64::MAX as f64)
+        .then(|| Duration::from_nanos(nanos as u64))
+}
@@ -0,0 +1,37 @@
+//! Exit statuses of the cs39 binary for command-line errors, which are
+//! reported before any demo is looked up.
+
+use std::process::{Command, Output};
+
+fn cs39(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cs39"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn cli_errors_exit_with_2() {
+    for args in [
+        &["frobnicate"][..],
+        &["run", "0", "3", "--verbose"],
+        &["cpu_test", "0", "3", "--max-threads"],
+        &["run", "0"],
+        &[],
+    ] {
+        let output = cs39(args);
+        assert_eq!(output.status.code(), Some(2), "cs39 {:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("[ERROR] "), "cs39 {:?}: {}", args, stderr);
+    }
+}
+
+#[test]
+fn help_exits_with_0() {
+    for args in [&["help"][..], &["help", "cpu_test"], &["cpu_test", "--help"]] {
+        let output = cs39(args);
+        assert!(output.status.success(), "cs39 {:?}", args);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("USAGE"),
+            "cs39 {:?}", args);
+    }
+}
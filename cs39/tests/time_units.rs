@@ -0,0 +1,174 @@
+//! Regression tests for time unit conversions, from the `Elapsed time` lines
+//! demos print through to the tables cs39 writes.
+//!
+//! `BestTimeMs` was once written as seconds divided by 1000, which put every
+//! table off by a factor of a million.
+
+extern crate cs39;
+extern crate csv;
+
+use cs39::{
+    cpu_test::{self, CpuTestRow},
+    size_test::{self, Dim, SizeTestRow},
+    kernel_sum_test::{self, KernelSumRow, parse_entire_run_line, parse_kernel_run_line},
+    quant::{demo_min_time, demo_times, parse_elapsed_time_line, ResourceUsage},
+    stats::Summary,
+    output::TableWriter,
+    units::{Millis, Secs},
+};
+use serde::Serialize;
+use std::{collections::HashMap, env, fs, process, time::Duration};
+
+const DEMO_OUTPUT: &[&str] = &[
+    "Running test iteration  1 [Elapsed time : 759.172ms]",
+    "Running test iteration  2 [Elapsed time : 152.506ms]",
+    "Running test iteration  3 [Elapsed time : 157.926ms]",
+    "Running test iteration  4 [Elapsed time : 154.616ms]",
+    "Running test iteration  5 [Elapsed time : 159.009ms]",
+];
+
+/// Write a row under a header with `TableWriter`, as the tasks do, to a
+/// temporary file named `name`, and read it back as a map from column name to
+/// value.
+fn write_row<R: Serialize>(
+    name: &str,
+    header: Vec<String>,
+    row: R,
+) -> HashMap<String, String> {
+    let path = env::temp_dir().join(format!("cs39-{}-{}.csv", process::id(), name));
+    let mut table = TableWriter::csv_file_with_header(&path, header).unwrap();
+    table.write(row).unwrap();
+    drop(table);
+
+    let mut reader = csv::Reader::from_path(&path).unwrap();
+    let columns = reader.headers().unwrap().clone();
+    let records: Vec<csv::StringRecord> = reader.records()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(records.len(), 1, "expected one row");
+    assert_eq!(columns.len(), records[0].len(), "header and row widths differ");
+    columns.iter()
+        .zip(records[0].iter())
+        .map(|(column, value)| (column.to_owned(), value.to_owned()))
+        .collect()
+}
+
+fn ms_column(row: &HashMap<String, String>, column: &str) -> f64 {
+    row[column].parse().unwrap()
+}
+
+#[test]
+fn elapsed_time_line_parses_as_milliseconds() {
+    assert_eq!(parse_elapsed_time_line(DEMO_OUTPUT[1]),
+        Some(Duration::from_micros(152_506)));
+    assert_eq!(parse_elapsed_time_line(
+        "Running test iteration 10 [Elapsed time : 3ms]"),
+        Some(Duration::from_millis(3)));
+    assert_eq!(parse_elapsed_time_line("[Elapsed time : 3ms]"), None);
+}
+
+#[test]
+fn demo_times_keep_every_iteration() {
+    let times = demo_times(DEMO_OUTPUT);
+    assert_eq!(times.len(), DEMO_OUTPUT.len());
+    assert_eq!(times[0], Duration::from_micros(759_172));
+    assert_eq!(demo_min_time(DEMO_OUTPUT), Some(Duration::from_micros(152_506)));
+}
+
+#[test]
+fn millis_round_trip_durations() {
+    assert_eq!(Millis::from(Duration::from_micros(152_506)), Millis(152.506));
+    assert_eq!(Millis::from(Duration::from_secs(2)), Millis(2000.0));
+    assert_eq!(Millis(152.506).to_duration(), Some(Duration::from_micros(152_506)));
+    assert_eq!(Secs::from(Duration::from_millis(1500)), Secs(1.5));
+    assert_eq!(Secs(1.5).to_duration(), Some(Duration::from_millis(1500)));
+    assert_eq!(Millis(-1.0).to_duration(), None);
+    assert_eq!(Millis(f64::NAN).to_duration(), None);
+}
+
+#[test]
+fn units_display_with_suffixes() {
+    assert_eq!(Millis(152.506).to_string(), "152.51ms");
+    assert_eq!(format!("{:.3}", Millis(152.506)), "152.506ms");
+    assert_eq!(Secs(1.5).to_string(), "1.50s");
+    assert_eq!(format!("{:.3}", Secs(1.5)), "1.500s");
+}
+
+#[test]
+fn cpu_test_row_writes_milliseconds() {
+    let times = demo_times(DEMO_OUTPUT);
+    let summary = Summary::new(&times, 1).unwrap();
+    assert_eq!(summary.samples, DEMO_OUTPUT.len() - 1);
+    let row = CpuTestRow {
+        threads: 4,
+        best_time_ms: Millis::from(summary.min),
+        summary,
+        spread: None,
+        usage: ResourceUsage {
+            user_time: Duration::from_millis(1580),
+            ..ResourceUsage::default()
+        },
+        counts: None,
+    };
+
+    let row = write_row("cpu_test", cpu_test::header(false), row);
+    assert_eq!(row["Threads"], "4");
+    assert_eq!(ms_column(&row, "BestTimeMs"), 152.506);
+    assert_eq!(ms_column(&row, "MedianMs"), 156.271);
+    assert_eq!(ms_column(&row, "UserTimeMs"), 1580.0);
+    assert_eq!(row["Runs"], "1");
+    assert_eq!(row["RunToRunStddevMs"], "");
+}
+
+#[test]
+fn size_test_row_writes_milliseconds() {
+    let times = demo_times(DEMO_OUTPUT);
+    let summary = Summary::new(&times, 0).unwrap();
+    let dims = vec![
+        Dim { name: "XDIM".to_owned(), size: 256 },
+        Dim { name: "YDIM".to_owned(), size: 128 },
+    ];
+    let row = SizeTestRow {
+        dims: dims.clone(),
+        product_size: 256 * 128,
+        data_size_bytes: 256 * 128 * 4,
+        data_size_string: "256×128 = 128 KiB".to_owned(),
+        fits_in: Some("L2".to_owned()),
+        best_time_ms: Millis::from(summary.min),
+        summary,
+        spread: None,
+        usage: ResourceUsage::default(),
+        counts: None,
+    };
+
+    let row = write_row("size_test", size_test::header(&dims, false), row);
+    assert_eq!(row["XSize"], "256");
+    assert_eq!(row["FitsIn"], "L2");
+    assert_eq!(ms_column(&row, "BestTimeMs"), 152.506);
+    assert_eq!(ms_column(&row, "MedianMs"), 157.926);
+    assert_eq!(row["Outliers"], "1");
+}
+
+#[test]
+fn kernel_sum_row_writes_milliseconds() {
+    let (name, kernel) = parse_kernel_run_line("[KERNEL ComputeLaplacian : Time = 12.5ms]")
+        .unwrap();
+    assert_eq!(name, "ComputeLaplacian");
+    assert_eq!(kernel, Duration::from_micros(12_500));
+    let total = parse_entire_run_line("[Entire Run : 1234.567ms]").unwrap();
+    assert_eq!(total, Duration::from_micros(1_234_567));
+
+    let row = KernelSumRow {
+        threads: 1,
+        total_time_ms: Millis::from(total),
+        kernel_sum_ms: Millis::from(kernel),
+        unaccounted_ms: total.checked_sub(kernel).map(Millis::from),
+        usage: ResourceUsage::default(),
+    };
+
+    let row = write_row("kernel_sum_test", kernel_sum_test::header(), row);
+    assert_eq!(ms_column(&row, "TotalTimeMs"), 1234.567);
+    assert_eq!(ms_column(&row, "KernelSumMs"), 12.5);
+    assert_eq!(ms_column(&row, "UnaccountedMs"), 1222.067);
+}